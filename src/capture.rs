@@ -1,25 +1,168 @@
+//! Screen captures through KWin, wlroots or the desktop portal.
+//!
+//! The capture functions are plain futures that bring no executor of their
+//! own: D-Bus runs on zbus' built-in async-io reactor, blocking Wayland work
+//! on a thread of its own, and timeouts on async-io timers. They can be
+//! awaited from any runtime, tokio included, or driven with `block_on` as the
+//! binary does.
+//!
+//! ```ignore
+//! #[tokio::main]
+//! async fn main() -> anyhow::Result<()> {
+//!     let backend = capture::detect_backend().await?;
+//!     let captured = backend.area(0, 0, 640, 480, None).await?;
+//!     println!("{captured:?}");
+//!     Ok(())
+//! }
+//! ```
 #![allow(dead_code)]
-use anyhow::Result;
+mod portal;
+mod windows;
+mod wlroots;
+
+pub use windows::Window;
+
+use anyhow::{anyhow, bail, Result};
+use async_io::Timer;
+use futures::{
+    channel::oneshot,
+    future::{self, Either},
+};
+use image::{imageops, RgbaImage};
 use libc::{self, c_int};
+use rayon::prelude::*;
+use smithay_client_toolkit::output::OutputInfo;
 use std::{
     collections::HashMap,
+    fmt,
     fs::File,
     future::Future,
     io::{self, Read},
-    os::fd::{FromRawFd, OwnedFd},
+    os::{
+        fd::{AsRawFd, FromRawFd, OwnedFd},
+        unix::fs::FileExt,
+    },
+    pin::pin,
+    sync::Mutex,
+    thread,
+    time::{Duration, Instant},
 };
+use wayland_client::protocol::wl_shm;
 use zbus::{
+    fdo::{self, DBusProxy},
+    names::BusName,
     proxy,
     zvariant::{Fd, OwnedValue, Value, Error},
     Connection,
 };
 
+use crate::logger::debug;
+use crate::selection::{output_physical_region, Area, Cancelled, Region};
+
+/// how long to wait for the compositor before giving up on a capture
+const CAPTURE_TIMEOUT: Duration = Duration::from_secs(10);
+/// how long KWin may stop writing to the memfd, once it started, before the
+/// image is taken as it is
+const MEMFD_STALL: Duration = Duration::from_millis(500);
+/// how long to wait for the user to pick something in an interactive capture
+const INTERACTIVE_TIMEOUT: Duration = Duration::from_secs(300);
+/// captures of at least this many RGBA bytes, about a million pixels, are
+/// repacked on all cores
+const PARALLEL_REPACK_SIZE: usize = 4 << 20;
+
+/// Options of KWin's capture methods.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct CaptureOptions {
+    /// capture at the outputs' physical resolution rather than logical size
+    pub native_resolution: bool,
+    /// draw the mouse cursor in
+    pub include_cursor: bool,
+    /// keep the window frame, for window captures
+    pub include_decoration: bool,
+    /// have KWin write to a memfd read in one go, rather than a pipe drained
+    /// as it is written, when memfds are available
+    pub memfd: bool,
+}
+impl Default for CaptureOptions {
+    fn default() -> Self {
+        Self {
+            native_resolution: true,
+            include_cursor: false,
+            include_decoration: false,
+            memfd: false,
+        }
+    }
+}
+impl CaptureOptions {
+    /// The `options` argument of the KWin methods.
+    pub fn to_dbus_map(self) -> HashMap<&'static str, Value<'static>> {
+        HashMap::from([
+            ("native-resolution", Value::from(self.native_resolution)),
+            ("include-cursor", Value::from(self.include_cursor)),
+            ("include-decoration", Value::from(self.include_decoration)),
+        ])
+    }
+}
+
 pub struct RawCaptured {
     pub width: u32,
     pub height: u32,
     pub scale: f64,
     pub buf: Vec<u8>,
 }
+impl fmt::Debug for RawCaptured {
+    // thousands of lines of pixels help nobody
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("RawCaptured")
+            .field("width", &self.width)
+            .field("height", &self.height)
+            .field("scale", &self.scale)
+            .field("bytes", &self.buf.len())
+            .finish()
+    }
+}
+impl RawCaptured {
+    /// Whether the capture has no pixels at all.
+    pub fn is_empty(&self) -> bool {
+        self.width == 0 || self.height == 0
+    }
+
+    /// A copy of the pixels as an image, `None` when the buffer is too short
+    /// for the size.
+    pub fn as_rgba_image(&self) -> Option<RgbaImage> {
+        RgbaImage::from_vec(self.width, self.height, self.buf.clone())
+    }
+
+    /// Like `as_rgba_image`, without copying the pixels.
+    pub fn into_rgba_image(self) -> Option<RgbaImage> {
+        RgbaImage::from_vec(self.width, self.height, self.buf)
+    }
+
+    /// Cut the area at `x`, `y` of size `w`x`h` out of this capture.
+    pub fn crop(&self, x: i32, y: i32, w: u32, h: u32) -> Result<RawCaptured> {
+        let (buf, width, height) = crop(&self.buf, self.width, self.height, x, y, w, h)
+            .ok_or(anyhow!("region is outside of the capture"))?;
+        Ok(RawCaptured {
+            width,
+            height,
+            scale: self.scale,
+            buf,
+        })
+    }
+
+    /// Cut the logical `region` out of this capture of the whole workspace,
+    /// whose top-left corner is at the logical `origin`.
+    pub fn crop_workspace(&self, origin: (i32, i32), region: Region) -> Result<RawCaptured> {
+        let scaled = |v: i64| (v as f64 * self.scale).round() as i64;
+        let x = scaled(region.x() as i64 - origin.0 as i64);
+        let y = scaled(region.y() as i64 - origin.1 as i64);
+        let w = scaled(region.width() as i64);
+        let h = scaled(region.height() as i64);
+        let to_i32 = |v: i64| i32::try_from(v).map_err(|_| anyhow!("region is outside of the capture"));
+        let to_u32 = |v: i64| u32::try_from(v).map_err(|_| anyhow!("region is outside of the capture"));
+        self.crop(to_i32(x)?, to_i32(y)?, to_u32(w)?, to_u32(h)?)
+    }
+}
 
 #[proxy(
     default_service = "org.kde.KWin.ScreenShot2",
@@ -37,134 +180,778 @@ trait KWin<'_> {
         y: i32,
         width: u32,
         height: u32,
-        options: HashMap<&str, &Value<'_>>,
+        options: HashMap<&str, Value<'_>>,
         pipe: Fd<'_>,
     ) -> zbus::Result<HashMap<String, OwnedValue>>;
 
     fn capture_active_screen(
         &self,
-        options: HashMap<&str, &Value<'_>>,
+        options: HashMap<&str, Value<'_>>,
         pipe: Fd<'_>,
     ) -> zbus::Result<HashMap<String, OwnedValue>>;
 
     fn capture_screen(
         &self,
         name: &str,
-        options: HashMap<&str, &Value<'_>>,
+        options: HashMap<&str, Value<'_>>,
         pipe: Fd<'_>,
     ) -> zbus::Result<HashMap<String, OwnedValue>>;
 
     fn capture_active_window(
         &self,
-        options: HashMap<&str, &Value<'_>>,
+        options: HashMap<&str, Value<'_>>,
         pipe: Fd<'_>,
     ) -> zbus::Result<HashMap<String, OwnedValue>>;
 
     fn capture_window(
         &self,
         handle: &str,
-        options: HashMap<&str, &Value<'_>>,
+        options: HashMap<&str, Value<'_>>,
         pipe: Fd<'_>,
     ) -> zbus::Result<HashMap<String, OwnedValue>>;
 
     fn capture_workspace(
         &self,
-        options: HashMap<&str, &Value<'_>>,
+        options: HashMap<&str, Value<'_>>,
         pipe: Fd<'_>,
     ) -> zbus::Result<HashMap<String, OwnedValue>>;
 
     fn capture_interactive(
         &self,
         kind: u32,
-        options: HashMap<&str, &Value<'_>>,
+        options: HashMap<&str, Value<'_>>,
         pipe: Fd<'_>,
     ) -> zbus::Result<HashMap<String, OwnedValue>>;
 }
 
-async fn with_kwin<F, Fut>(f: F) -> Result<RawCaptured>
+/// Run a KWin capture method `f`, handing it a memfd when `memfd` is set and
+/// one can be made, a pipe otherwise, and read the image it writes.
+async fn with_kwin<F, Fut>(memfd: bool, f: F) -> Result<RawCaptured>
 where
     F: FnOnce(Connection, OwnedFd) -> Fut,
     Fut: Future<Output = zbus::Result<HashMap<String, OwnedValue>>>,
 {
-    let conn = Connection::session().await?;
+    with_kwin_timeout(CAPTURE_TIMEOUT, memfd, f).await
+}
+
+/// Like `with_kwin`, waiting up to `timeout` for KWin to reply.
+async fn with_kwin_timeout<F, Fut>(timeout: Duration, use_memfd: bool, f: F) -> Result<RawCaptured>
+where
+    F: FnOnce(Connection, OwnedFd) -> Fut,
+    Fut: Future<Output = zbus::Result<HashMap<String, OwnedValue>>>,
+{
+    let conn = session().await?;
+    let started = Instant::now();
+    let call = |fd| async move {
+        with_timeout(f(conn, fd), timeout, "the capture reply")
+            .await?
+            .map_err(kwin_error)
+    };
+    let memfd = match use_memfd.then(memfd).transpose() {
+        Ok(memfd) => memfd,
+        // e.g. a kernel without memfds or a sandbox denying them
+        Err(err) => {
+            debug!("capturing through a pipe, no memfd: {err}");
+            None
+        }
+    };
+    let (captured, transfer) = match memfd {
+        Some(memfd) => {
+            let captured = call(OwnedFd::from(memfd.try_clone()?)).await?;
+            (captured, Transfer::Memfd(memfd))
+        }
+        None => {
+            let (captured, reading) = with_pipe(call).await?;
+            (captured, Transfer::Pipe(reading))
+        }
+    };
+
+    let info = ReplyInfo::parse(&captured);
+    let replied = started.elapsed();
+    debug!("KWin replied after {replied:?}: {info:?}");
+    if info.width == 0 || info.height == 0 {
+        bail!("capture returned empty image (region may be invalid or denied)");
+    }
+    let format = PixelFormat::from_qimage(info.format)?;
+
+    // wait for the data, KWin writes it after replying
+    let stride = if info.stride == 0 { info.width * 4 } else { info.stride };
+    let buf = match transfer {
+        Transfer::Memfd(memfd) => {
+            let size = image_size(info.width, info.height, stride);
+            let read = read_memfd(memfd, size, MEMFD_STALL);
+            with_timeout(read, CAPTURE_TIMEOUT, "the captured image data").await??
+        }
+        // returning early, on a timeout too, drops `reading` and stops the reader
+        Transfer::Pipe(reading) => {
+            with_timeout(reading.rx, CAPTURE_TIMEOUT, "the captured image data")
+                .await?
+                .map_err(|_| anyhow!("pipe reader exited unexpectedly"))??
+        }
+    };
+    debug!(
+        "read {} bytes of image data {:?} after the reply",
+        buf.len(),
+        started.elapsed() - replied
+    );
+    check_image_size(buf.len(), info.width, info.height, stride)?;
+
+    let raw = RawCaptured {
+        width: info.width,
+        height: info.height,
+        scale: info.scale,
+        buf: repack(&buf, info.width, info.height, stride, format),
+    };
+
+    Ok(raw)
+}
+
+/// How KWin describes the image it writes for us.
+#[derive(Debug, Clone, PartialEq)]
+struct ReplyInfo {
+    format: u32,
+    width: u32,
+    height: u32,
+    /// 0 when the rows are not padded
+    stride: u32,
+    /// image pixels per logical pixel, 1 when not reported
+    scale: f64,
+}
+impl ReplyInfo {
+    fn parse(captured: &HashMap<String, OwnedValue>) -> Self {
+        fn extract<'a, T>(captured: &'a HashMap<String, OwnedValue>, key: &str, default: T) -> T
+        where
+            T: Clone,
+            &'a T: TryFrom<&'a Value<'a>> + 'a,
+            <&'a T as TryFrom<&'a Value<'a>>>::Error: Into<Error>,
+        {
+            captured
+                .get(key)
+                .map_or(&default, |v| v.downcast_ref().unwrap_or(&default))
+                .to_owned()
+        }
+
+        let scale: f64 = extract(captured, "scale", 1.);
+        Self {
+            format: extract(captured, "format", QIMAGE_FORMAT_ARGB32),
+            width: extract(captured, "width", 0),
+            height: extract(captured, "height", 0),
+            stride: extract(captured, "stride", 0),
+            scale: if scale > 0. { scale } else { 1. },
+        }
+    }
+}
+
+/// Put captures of parts of an image of `width`x`height` at their target,
+/// resizing those taken at a different scale.
+fn stitch(width: u32, height: u32, parts: Vec<(RawCaptured, Region)>) -> Result<RawCaptured> {
+    let mut image = RgbaImage::new(width, height);
+    let mut scale = 1.;
+    for (part, target) in parts {
+        // scaled like the part at the origin
+        if (target.x(), target.y()) == (0, 0) {
+            scale = part.scale;
+        }
+        let mut part = RgbaImage::from_vec(part.width, part.height, part.buf)
+            .ok_or(anyhow!("captured buffer does not match its size"))?;
+        if part.dimensions() != (target.width(), target.height()) {
+            part = imageops::resize(
+                &part,
+                target.width(),
+                target.height(),
+                imageops::FilterType::Triangle,
+            );
+        }
+        imageops::replace(&mut image, &part, target.x() as i64, target.y() as i64);
+    }
+    Ok(RawCaptured {
+        width,
+        height,
+        scale,
+        buf: image.into_raw(),
+    })
+}
+
+/// Turn a failed KWin call into an error fit for users.
+fn kwin_error(err: zbus::Error) -> anyhow::Error {
+    if kwin_cancelled(&err) {
+        Cancelled.into()
+    } else if kwin_missing(&err) {
+        anyhow!(
+            "KWin's org.kde.KWin.ScreenShot2 interface is not available, \
+            the compositor may be unsupported ({err})"
+        )
+    } else {
+        err.into()
+    }
+}
+
+/// Where KWin writes the image to.
+enum Transfer {
+    /// read in one go once complete
+    Memfd(File),
+    /// drained while KWin writes
+    Pipe(PipeReading),
+}
+
+/// Create an anonymous file living in memory, closed on drop.
+fn memfd() -> io::Result<File> {
+    // SAFETY: the name is a valid C string, only read during the call
+    let fd = unsafe { libc::memfd_create(c"rq-capture".as_ptr(), libc::MFD_CLOEXEC) };
+    if fd < 0 {
+        return Err(io::Error::last_os_error());
+    }
+    // SAFETY: memfd_create succeeded, the descriptor is open and owned by nobody else
+    Ok(unsafe { File::from_raw_fd(fd) })
+}
+
+/// Everything written to `memfd`, once it holds at least `size` bytes or
+/// stopped growing for `stall` after the first write.
+///
+/// Nothing tells when the writer is done, unlike the end of a pipe, so this
+/// checks the size until the whole image is there. A writer giving up early
+/// leaves less, for the caller to tell. One that never starts is left to the
+/// caller's timeout.
+async fn read_memfd(memfd: File, size: usize, stall: Duration) -> io::Result<Vec<u8>> {
+    let (mut last_len, mut grown) = (0, None);
+    loop {
+        let len = memfd.metadata()?.len() as usize;
+        if len > last_len {
+            (last_len, grown) = (len, Some(Instant::now()));
+        }
+        let stalled = grown.is_some_and(|at: Instant| at.elapsed() >= stall);
+        if len >= size || stalled {
+            let mut buf = vec![0; len];
+            // the offset is shared with the writer, which left it at the end
+            memfd.read_exact_at(&mut buf, 0)?;
+            return Ok(buf);
+        }
+        Timer::after(Duration::from_millis(2)).await;
+    }
+}
+
+/// Create a pipe, both ends closed on drop.
+fn pipe() -> io::Result<(File, OwnedFd)> {
     let mut fds: [c_int; 2] = [0; 2];
     let res = unsafe { libc::pipe2(fds.as_mut_ptr(), libc::O_CLOEXEC) };
     if res != 0 {
-        return Err(io::Error::last_os_error().into());
+        return Err(io::Error::last_os_error());
+    }
+    // SAFETY: pipe2 succeeded, both descriptors are open and owned by nobody else
+    let (reader, writer) = unsafe { (File::from_raw_fd(fds[0]), OwnedFd::from_raw_fd(fds[1])) };
+    Ok((reader, writer))
+}
+
+/// Everything written to a pipe, read on a thread.
+struct PipeReading {
+    /// yields the data once every write end is closed
+    rx: oneshot::Receiver<io::Result<Vec<u8>>>,
+    /// dropped to make the reader give up, e.g. when KWin still holds a
+    /// write end after the call failed
+    _abort: OwnedFd,
+}
+
+/// Run `f` with the write end of a new pipe, reading everything written to it
+/// on a thread.
+///
+/// `f` owns the write end and the reader thread the read end, so both are
+/// closed exactly once whether `f` succeeds or not. When `f` fails the reader
+/// stops right away instead of waiting for a write that may never come.
+async fn with_pipe<F, Fut, T>(f: F) -> Result<(T, PipeReading)>
+where
+    F: FnOnce(OwnedFd) -> Fut,
+    Fut: Future<Output = Result<T>>,
+{
+    let (reader, writer) = pipe()?;
+    let (aborted, abort) = pipe()?;
+
+    // drain the pipe while the call is in flight, KWin blocks once the pipe buffer is full
+    let (tx, rx) = oneshot::channel();
+    thread::spawn(move || {
+        let _ = tx.send(read_until_aborted(reader, aborted));
+    });
+
+    let out = f(writer).await?;
+    Ok((out, PipeReading { rx, _abort: abort }))
+}
+
+/// Read `reader` to the end, or fail once the write end of `aborted` closes.
+fn read_until_aborted(mut reader: File, aborted: File) -> io::Result<Vec<u8>> {
+    let mut buf = Vec::new();
+    let mut chunk = vec![0; 64 * 1024];
+    loop {
+        let mut fds = [reader.as_raw_fd(), aborted.as_raw_fd()].map(|fd| libc::pollfd {
+            fd,
+            events: libc::POLLIN,
+            revents: 0,
+        });
+        // SAFETY: both descriptors stay open for the whole call
+        if unsafe { libc::poll(fds.as_mut_ptr(), fds.len() as libc::nfds_t, -1) } < 0 {
+            let err = io::Error::last_os_error();
+            if err.kind() == io::ErrorKind::Interrupted {
+                continue;
+            }
+            return Err(err);
+        }
+        // what was written still counts when both are ready
+        if fds[0].revents != 0 {
+            match reader.read(&mut chunk) {
+                Ok(0) => return Ok(buf),
+                Ok(n) => buf.extend_from_slice(&chunk[..n]),
+                Err(err) if err.kind() == io::ErrorKind::Interrupted => {}
+                Err(err) => return Err(err),
+            }
+        } else if fds[1].revents != 0 {
+            return Err(io::Error::other("reading the capture was aborted"));
+        }
     }
-    let fd = unsafe { OwnedFd::from_raw_fd(fds[1]) };
-    let captured = f(conn, fd).await?;
-    unsafe {
-        libc::close(fds[1]);
+}
+
+/// Whether `err` is KWin telling the user dismissed an interactive capture.
+fn kwin_cancelled(err: &zbus::Error) -> bool {
+    matches!(
+        err,
+        zbus::Error::MethodError(name, _, _)
+            if name.as_str() == "org.kde.KWin.ScreenShot2.Error.Cancelled"
+    )
+}
+
+/// Whether `err` means nothing on the bus implements KWin's screenshot interface.
+fn kwin_missing(err: &zbus::Error) -> bool {
+    const MISSING: &[&str] = &[
+        "org.freedesktop.DBus.Error.ServiceUnknown",
+        "org.freedesktop.DBus.Error.NameHasNoOwner",
+        "org.freedesktop.DBus.Error.UnknownObject",
+        "org.freedesktop.DBus.Error.UnknownInterface",
+        "org.freedesktop.DBus.Error.UnknownMethod",
+    ];
+    match err {
+        zbus::Error::InterfaceNotFound => true,
+        zbus::Error::MethodError(name, _, _) => MISSING.contains(&name.as_str()),
+        zbus::Error::FDO(err) => matches!(
+            **err,
+            fdo::Error::ServiceUnknown(_)
+                | fdo::Error::NameHasNoOwner(_)
+                | fdo::Error::UnknownObject(_)
+                | fdo::Error::UnknownInterface(_)
+                | fdo::Error::UnknownMethod(_)
+        ),
+        _ => false,
     }
+}
 
-    fn extract<'a, T>(captured: &'a HashMap<String, OwnedValue>, key: &str, default: T) -> T
-    where
-        T: Clone,
-        &'a T: TryFrom<&'a Value<'a>> + 'a,
-        <&'a T as TryFrom<&'a Value<'a>>>::Error: Into<Error>,
-    {
-        captured
-            .get(key)
-            .map_or(&default, |v| v.downcast_ref().unwrap_or(&default))
-            .to_owned()
+/// Give up on `fut` when it has not completed within `timeout`.
+async fn with_timeout<T>(
+    fut: impl Future<Output = T>,
+    timeout: Duration,
+    what: &str,
+) -> Result<T> {
+    match future::select(pin!(fut), Timer::after(timeout)).await {
+        Either::Left((out, _)) => Ok(out),
+        Either::Right(_) => bail!("timed out after {}s waiting for {what}", timeout.as_secs()),
     }
+}
 
-    let _oformat: u32 = extract(&captured, "format", 0);
-    let owidth: u32 = extract(&captured, "width", 0);
-    let oheight: u32 = extract(&captured, "height", 0);
-    let _ostride: u32 = extract(&captured, "stride", 0);
-    let oscale: f64 = extract(&captured, "scale", 0.);
+// QImage::Format values, which is what KWin reports in `format`
+const QIMAGE_FORMAT_RGB32: u32 = 4;
+const QIMAGE_FORMAT_ARGB32: u32 = 5;
+const QIMAGE_FORMAT_ARGB32_PREMULTIPLIED: u32 = 6;
+const QIMAGE_FORMAT_RGBX8888: u32 = 16;
+const QIMAGE_FORMAT_RGBA8888: u32 = 17;
+const QIMAGE_FORMAT_RGBA8888_PREMULTIPLIED: u32 = 18;
 
-    // read to buf
-    let mut f = unsafe { File::from_raw_fd(fds[0]) };
-    let data_size = (owidth * 4 * oheight) as usize;
-    let mut buf = Vec::with_capacity(data_size);
-    {
-        let _size = f.read_to_end(&mut buf)?;
+/// 32bpp pixel layouts, named after their DRM/wl_shm fourcc.
+/// The fourcc describes a little-endian word, so `Argb8888` is stored as B, G, R, A.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum PixelFormat {
+    Argb8888,
+    Xrgb8888,
+    Abgr8888,
+    Xbgr8888,
+}
+impl PixelFormat {
+    fn from_qimage(format: u32) -> Result<Self> {
+        // premultiplied alpha is kept as is, screen contents are opaque anyway
+        match format {
+            QIMAGE_FORMAT_RGB32 => Ok(Self::Xrgb8888),
+            QIMAGE_FORMAT_ARGB32 | QIMAGE_FORMAT_ARGB32_PREMULTIPLIED => Ok(Self::Argb8888),
+            QIMAGE_FORMAT_RGBX8888 => Ok(Self::Xbgr8888),
+            QIMAGE_FORMAT_RGBA8888 | QIMAGE_FORMAT_RGBA8888_PREMULTIPLIED => Ok(Self::Abgr8888),
+            _ => bail!("unsupported capture pixel format: {format}"),
+        }
     }
 
-    let raw = RawCaptured {
-        width: owidth,
-        height: oheight,
-        scale: oscale,
-        buf: buf
-            .chunks_exact(4)
-            .flat_map(|bgra| [bgra[2], bgra[1], bgra[0], bgra[3]])
-            .collect::<Vec<u8>>(),
-    };
+    fn from_wl_shm(format: wl_shm::Format) -> Result<Self> {
+        match format {
+            wl_shm::Format::Argb8888 => Ok(Self::Argb8888),
+            wl_shm::Format::Xrgb8888 => Ok(Self::Xrgb8888),
+            wl_shm::Format::Abgr8888 => Ok(Self::Abgr8888),
+            wl_shm::Format::Xbgr8888 => Ok(Self::Xbgr8888),
+            _ => bail!("unsupported capture pixel format: {format:?}"),
+        }
+    }
 
-    Ok(raw)
+    #[inline]
+    fn to_rgba(self, px: &[u8]) -> [u8; 4] {
+        match self {
+            Self::Argb8888 => [px[2], px[1], px[0], px[3]],
+            Self::Xrgb8888 => [px[2], px[1], px[0], 0xff],
+            Self::Abgr8888 => [px[0], px[1], px[2], px[3]],
+            Self::Xbgr8888 => [px[0], px[1], px[2], 0xff],
+        }
+    }
+}
+
+/// Bytes of an image of `height` rows `stride` apart, the last one may be
+/// left unpadded.
+fn image_size(width: u32, height: u32, stride: u32) -> usize {
+    let rows = (height as usize).saturating_sub(1);
+    stride as usize * rows + width as usize * 4
+}
+
+/// Fail unless `len` bytes hold the whole image, the compositor may write
+/// less when it runs into trouble halfway.
+fn check_image_size(len: usize, width: u32, height: u32, stride: u32) -> Result<()> {
+    if (stride as u64) < width as u64 * 4 {
+        bail!("capture rows are {stride} bytes apart, too few for {width} pixels");
+    }
+    let expected = image_size(width, height, stride);
+    if len < expected {
+        bail!("capture is truncated, got {len} of the {expected} bytes of a {width}x{height} image");
+    }
+    Ok(())
 }
 
-pub async fn workspace() -> Result<RawCaptured> {
-    let native_resolution = Value::from(true);
-    let options = HashMap::from([("native-resolution", &native_resolution)]);
-    let img = with_kwin(|conn, fd| async move {
+/// Convert `height` rows of `format` pixels, `stride` bytes apart, into tightly
+/// packed RGBA. Padding at the end of each row is dropped.
+///
+/// Large buffers are converted a row per task on all cores.
+fn repack(buf: &[u8], width: u32, height: u32, stride: u32, format: PixelFormat) -> Vec<u8> {
+    let (row_size, rows) = (width as usize * 4, height as usize);
+    let complete = stride as usize >= row_size && buf.len() >= image_size(width, height, stride);
+    if !complete || row_size * rows < PARALLEL_REPACK_SIZE {
+        return repack_serial(buf, width, height, stride, format);
+    }
+
+    let mut out = vec![0; row_size * rows];
+    out.par_chunks_mut(row_size)
+        .zip(buf.par_chunks(stride as usize))
+        .for_each(|(out, row)| {
+            for (out, px) in out.chunks_exact_mut(4).zip(row.chunks_exact(4)) {
+                out.copy_from_slice(&format.to_rgba(px));
+            }
+        });
+    out
+}
+
+fn repack_serial(buf: &[u8], width: u32, height: u32, stride: u32, format: PixelFormat) -> Vec<u8> {
+    let row_size = width as usize * 4;
+    buf.chunks(stride as usize)
+        .take(height as usize)
+        .flat_map(|row| row[..row_size.min(row.len())].chunks_exact(4))
+        .flat_map(|px| format.to_rgba(px))
+        .collect::<Vec<u8>>()
+}
+
+pub async fn workspace(options: Option<CaptureOptions>) -> Result<RawCaptured> {
+    let options = options.unwrap_or_default();
+    let img = with_kwin(options.memfd, |conn, fd| async move {
         let proxy = KWinProxy::new(&conn).await?;
-        proxy.capture_workspace(options, fd.into()).await
+        proxy.capture_workspace(options.to_dbus_map(), fd.into()).await
     })
     .await?;
     Ok(img)
 }
 
-pub async fn area(x: i32, y: i32, w: u32, h: u32) -> Result<RawCaptured> {
-    let native_resolution = Value::from(true);
-    let options = HashMap::from([("native-resolution", &native_resolution)]);
-    let img = with_kwin(|conn, fd| async move {
+/// Cut the `w`x`h` area at `x`, `y` out of a tightly packed RGBA image, clipped
+/// to the image. Returns the pixels with their width and height, `None` when
+/// nothing is left.
+fn crop(
+    buf: &[u8],
+    width: u32,
+    height: u32,
+    x: i32,
+    y: i32,
+    w: u32,
+    h: u32,
+) -> Option<(Vec<u8>, u32, u32)> {
+    let left = x.clamp(0, width as i32) as usize;
+    let top = y.clamp(0, height as i32) as usize;
+    let right = (x as i64 + w as i64).clamp(0, width as i64) as usize;
+    let bottom = (y as i64 + h as i64).clamp(0, height as i64) as usize;
+    if left >= right || top >= bottom {
+        return None;
+    }
+
+    let row_size = width as usize * 4;
+    let out = buf
+        .chunks_exact(row_size)
+        .skip(top)
+        .take(bottom - top)
+        .flat_map(|row| &row[left * 4..right * 4])
+        .copied()
+        .collect();
+    Some((out, (right - left) as u32, (bottom - top) as u32))
+}
+
+/// Run blocking `f` on its own thread, so callers' executors are not stalled.
+pub(crate) async fn unblock<T, F>(f: F) -> Result<T>
+where
+    T: Send + 'static,
+    F: FnOnce() -> Result<T> + Send + 'static,
+{
+    let (tx, rx) = oneshot::channel();
+    thread::spawn(move || {
+        let _ = tx.send(f());
+    });
+    rx.await.map_err(|_| anyhow!("capture thread exited unexpectedly"))?
+}
+
+/// The session bus, connected to once and shared by the captures after, e.g.
+/// those repeated with --count.
+async fn session() -> zbus::Result<Connection> {
+    static SESSION: Mutex<Option<Connection>> = Mutex::new(None);
+    if let Some(conn) = SESSION.lock().unwrap().clone() {
+        return Ok(conn);
+    }
+    // not held across the await, a racing caller may connect too and lose
+    let conn = Connection::session().await?;
+    Ok(SESSION.lock().unwrap().get_or_insert(conn).clone())
+}
+
+/// Whether KWin's ScreenShot2 service is on the session bus.
+async fn kwin_available() -> bool {
+    async fn has_owner() -> Result<bool> {
+        let conn = session().await?;
+        let dbus = DBusProxy::new(&conn).await?;
+        let name = BusName::try_from("org.kde.KWin.ScreenShot2")?;
+        Ok(dbus.name_has_owner(name).await?)
+    }
+    has_owner().await.unwrap_or(false)
+}
+
+/// The compositor interface used to take captures.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Backend {
+    /// KWin's `org.kde.KWin.ScreenShot2` D-Bus interface
+    KWin,
+    /// wlroots `zwlr_screencopy_manager_v1` Wayland protocol
+    Wlroots,
+    /// `org.freedesktop.portal.Screenshot` desktop portal
+    Portal,
+}
+impl Backend {
+    /// Capture the area at `x`, `y` of size `w`x`h`. Backends other than KWin
+    /// only honor the options they support.
+    pub async fn area(
+        self,
+        x: i32,
+        y: i32,
+        w: u32,
+        h: u32,
+        options: Option<CaptureOptions>,
+    ) -> Result<RawCaptured> {
+        match self {
+            Self::KWin => area(x, y, w, h, options).await,
+            Self::Wlroots => {
+                let include_cursor = options.unwrap_or_default().include_cursor;
+                unblock(move || wlroots::area(x, y, w, h, include_cursor)).await
+            }
+            Self::Portal => portal::area(x, y, w, h).await,
+        }
+    }
+
+    /// Capture `area`, piece by piece when it spans several outputs.
+    ///
+    /// Without `native_resolution` KWin takes it in one go, in logical pixels.
+    pub async fn capture(self, area: &Area, options: Option<CaptureOptions>) -> Result<RawCaptured> {
+        let logical = self == Self::KWin && !options.unwrap_or_default().native_resolution;
+        let region = if logical { area.logical } else { area.region };
+        if area.pieces.is_empty() || logical {
+            return self
+                .area(
+                    region.x(),
+                    region.y(),
+                    region.width(),
+                    region.height(),
+                    options,
+                )
+                .await;
+        }
+        let mut parts = Vec::with_capacity(area.pieces.len());
+        for piece in &area.pieces {
+            let source = piece.source;
+            let captured = self
+                .area(
+                    source.x(),
+                    source.y(),
+                    source.width(),
+                    source.height(),
+                    options,
+                )
+                .await?;
+            parts.push((captured, piece.target));
+        }
+        stitch(region.width(), region.height(), parts)
+    }
+
+    /// Capture everything shown on `output`.
+    pub async fn screen(
+        self,
+        output: &OutputInfo,
+        options: Option<CaptureOptions>,
+    ) -> Result<RawCaptured> {
+        match (self, &output.name) {
+            (Self::KWin, Some(name)) => screen(name, options).await,
+            _ => {
+                let region = output_physical_region(output)
+                    .ok_or(anyhow!("output has no known geometry"))?;
+                self.area(
+                    region.x(),
+                    region.y(),
+                    region.width(),
+                    region.height(),
+                    options,
+                )
+                .await
+            }
+        }
+    }
+
+    /// Let the compositor ask the user for a window or screen, see `interactive`.
+    pub async fn interactive(
+        self,
+        kind: u32,
+        options: Option<CaptureOptions>,
+    ) -> Result<RawCaptured> {
+        match self {
+            Self::KWin => interactive(kind, options).await,
+            _ => bail!("interactive capture is only supported with KWin"),
+        }
+    }
+
+    /// The windows on the current desktop, topmost last.
+    pub async fn windows(self) -> Result<Vec<Window>> {
+        match self {
+            Self::KWin => windows::list().await,
+            _ => bail!("listing windows is only supported with KWin"),
+        }
+    }
+
+    /// Capture one of the `windows`, by its handle.
+    pub async fn window(self, handle: &str, options: Option<CaptureOptions>) -> Result<RawCaptured> {
+        match self {
+            Self::KWin => window(handle, options).await,
+            _ => bail!("capturing a window is only supported with KWin"),
+        }
+    }
+
+    /// Capture the output the compositor considers active.
+    pub async fn active_screen(self, options: Option<CaptureOptions>) -> Result<RawCaptured> {
+        match self {
+            Self::KWin => active_screen(options).await,
+            _ => bail!("capturing the active screen is only supported with KWin"),
+        }
+    }
+
+    /// Capture everything on screen.
+    pub async fn workspace(self, options: Option<CaptureOptions>) -> Result<RawCaptured> {
+        match self {
+            Self::KWin => workspace(options).await,
+            Self::Wlroots => bail!("capturing the whole workspace is not supported with wlroots"),
+            Self::Portal => portal::workspace().await,
+        }
+    }
+}
+
+/// Pick the first available backend: KWin, then wlroots, then the desktop portal.
+pub async fn detect_backend() -> Result<Backend> {
+    if kwin_available().await {
+        return Ok(Backend::KWin);
+    }
+    if unblock(|| Ok(wlroots::available())).await? {
+        return Ok(Backend::Wlroots);
+    }
+    if portal::available().await {
+        return Ok(Backend::Portal);
+    }
+    bail!(
+        "no capture backend available, tried: \
+        org.kde.KWin.ScreenShot2 on the session bus, \
+        zwlr_screencopy_manager_v1 on the Wayland display, \
+        org.freedesktop.portal.Screenshot on the session bus"
+    )
+}
+
+pub async fn area(x: i32, y: i32, w: u32, h: u32, options: Option<CaptureOptions>) -> Result<RawCaptured> {
+    let options = options.unwrap_or_default();
+    let img = with_kwin(options.memfd, |conn, fd| async move {
+        let proxy = KWinProxy::new(&conn).await?;
+        proxy.capture_area(x, y, w, h, options.to_dbus_map(), fd.into()).await
+    })
+    .await?;
+    Ok(img)
+}
+
+/// Capture the screen KWin considers active, the one with the pointer or focus.
+pub async fn active_screen(options: Option<CaptureOptions>) -> Result<RawCaptured> {
+    let options = options.unwrap_or_default();
+    let img = with_kwin(options.memfd, |conn, fd| async move {
+        let proxy = KWinProxy::new(&conn).await?;
+        proxy.capture_active_screen(options.to_dbus_map(), fd.into()).await
+    })
+    .await?;
+    Ok(img)
+}
+
+/// `kind` of `interactive`: let the user click a window.
+pub const INTERACTIVE_WINDOW: u32 = 0;
+/// `kind` of `interactive`: let the user click a screen.
+pub const INTERACTIVE_SCREEN: u32 = 1;
+
+/// Let KWin ask the user what to capture, a window or a screen depending on
+/// `kind`. Fails with `Cancelled` when the user dismisses it.
+pub async fn interactive(kind: u32, options: Option<CaptureOptions>) -> Result<RawCaptured> {
+    let options = options.unwrap_or_default();
+    let img = with_kwin_timeout(INTERACTIVE_TIMEOUT, options.memfd, |conn, fd| async move {
+        let proxy = KWinProxy::new(&conn).await?;
+        proxy.capture_interactive(kind, options.to_dbus_map(), fd.into()).await
+    })
+    .await?;
+    Ok(img)
+}
+
+pub async fn screen(name: &str, options: Option<CaptureOptions>) -> Result<RawCaptured> {
+    let options = options.unwrap_or_default();
+    let img = with_kwin(options.memfd, |conn, fd| async move {
+        let proxy = KWinProxy::new(&conn).await?;
+        proxy.capture_screen(name, options.to_dbus_map(), fd.into()).await
+    })
+    .await?;
+    Ok(img)
+}
+
+pub async fn active_window(options: Option<CaptureOptions>) -> Result<RawCaptured> {
+    let options = options.unwrap_or_default();
+    let img = with_kwin(options.memfd, |conn, fd| async move {
         let proxy = KWinProxy::new(&conn).await?;
-        proxy.capture_area(x, y, w, h, options, fd.into()).await
+        proxy.capture_active_window(options.to_dbus_map(), fd.into()).await
     })
     .await?;
     Ok(img)
 }
 
-pub async fn screen(name: &str) -> Result<RawCaptured> {
-    let native_resolution = Value::from(true);
-    let options = HashMap::from([("native-resolution", &native_resolution)]);
-    let img = with_kwin(|conn, fd| async move {
+/// Capture the window identified by `handle`.
+///
+/// The handle is KWin's internal window id, a UUID such as
+/// `{0c3bf2b6-5d36-4d6e-9a4b-8f6e4a2d3c11}`. It is the `uuid` field returned by
+/// `org.kde.KWin /KWin queryWindowInfo` (which lets the user click a window),
+/// or `internalId` of a window object in a KWin script.
+pub async fn window(handle: &str, options: Option<CaptureOptions>) -> Result<RawCaptured> {
+    let options = options.unwrap_or_default();
+    let img = with_kwin(options.memfd, |conn, fd| async move {
         let proxy = KWinProxy::new(&conn).await?;
-        proxy.capture_screen(name, options, fd.into()).await
+        proxy.capture_window(handle, options.to_dbus_map(), fd.into()).await
     })
     .await?;
     Ok(img)
@@ -176,10 +963,346 @@ mod tests {
     use futures::executor::block_on;
     use image::{ImageBuffer, Rgba};
 
+    #[test]
+    fn test_repack_padded_rows() {
+        // 2x2 BGRA image, each row padded to 12 bytes
+        let buf = [
+            1, 2, 3, 4, 5, 6, 7, 8, 0xff, 0xff, 0xff, 0xff, //
+            9, 10, 11, 12, 13, 14, 15, 16, 0xff, 0xff, 0xff, 0xff,
+        ];
+        let out = repack(&buf, 2, 2, 12, PixelFormat::Argb8888);
+        assert_eq!(out.len(), 2 * 2 * 4);
+        assert_eq!(
+            out,
+            [3, 2, 1, 4, 7, 6, 5, 8, 11, 10, 9, 12, 15, 14, 13, 16]
+        );
+    }
+
+    #[test]
+    fn test_repack_parallel() {
+        // padded rows, large enough to be split across threads
+        let (width, height, stride) = (1031, 1024, 1031 * 4 + 20);
+        assert!((width * height * 4) as usize >= PARALLEL_REPACK_SIZE);
+        let buf = (0..stride * height).map(|i| (i * 7 % 251) as u8).collect::<Vec<_>>();
+        for format in [PixelFormat::Argb8888, PixelFormat::Xbgr8888] {
+            assert_eq!(
+                repack(&buf, width, height, stride, format),
+                repack_serial(&buf, width, height, stride, format)
+            );
+        }
+        // a truncated buffer keeps the serial behavior
+        let short = &buf[..buf.len() - 100];
+        assert_eq!(
+            repack(short, width, height, stride, PixelFormat::Argb8888),
+            repack_serial(short, width, height, stride, PixelFormat::Argb8888)
+        );
+    }
+
+    #[test]
+    fn test_check_image_size() {
+        // 2x2 with rows padded to 12 bytes, the last one needn't be
+        assert!(check_image_size(24, 2, 2, 12).is_ok());
+        assert!(check_image_size(20, 2, 2, 12).is_ok());
+        let truncated = check_image_size(19, 2, 2, 12).unwrap_err();
+        assert_eq!(
+            truncated.to_string(),
+            "capture is truncated, got 19 of the 20 bytes of a 2x2 image"
+        );
+        // rows overlapping each other
+        assert!(check_image_size(64, 2, 2, 4).is_err());
+    }
+
+    #[test]
+    fn test_repack_tight_rows() {
+        let buf = [1, 2, 3, 4, 5, 6, 7, 8];
+        assert_eq!(
+            repack(&buf, 1, 2, 4, PixelFormat::Argb8888),
+            [3, 2, 1, 4, 7, 6, 5, 8]
+        );
+    }
+
+    #[test]
+    fn test_repack_formats() {
+        let buf = [1, 2, 3, 4];
+        let rgba = |format| repack(&buf, 1, 1, 4, format);
+        assert_eq!(rgba(PixelFormat::Argb8888), [3, 2, 1, 4]);
+        assert_eq!(rgba(PixelFormat::Xrgb8888), [3, 2, 1, 0xff]);
+        assert_eq!(rgba(PixelFormat::Abgr8888), [1, 2, 3, 4]);
+        assert_eq!(rgba(PixelFormat::Xbgr8888), [1, 2, 3, 0xff]);
+    }
+
+    #[test]
+    fn test_pixel_format_from_qimage() {
+        assert_eq!(
+            PixelFormat::from_qimage(QIMAGE_FORMAT_ARGB32_PREMULTIPLIED).unwrap(),
+            PixelFormat::Argb8888
+        );
+        assert_eq!(
+            PixelFormat::from_qimage(QIMAGE_FORMAT_RGB32).unwrap(),
+            PixelFormat::Xrgb8888
+        );
+        assert_eq!(
+            PixelFormat::from_qimage(QIMAGE_FORMAT_RGBA8888).unwrap(),
+            PixelFormat::Abgr8888
+        );
+        assert!(PixelFormat::from_qimage(0).is_err());
+    }
+
+    #[test]
+    fn test_capture_options_to_dbus_map() {
+        let map = CaptureOptions::default().to_dbus_map();
+        assert_eq!(map["native-resolution"], Value::from(true));
+        assert_eq!(map["include-cursor"], Value::from(false));
+        assert_eq!(map["include-decoration"], Value::from(false));
+
+        let logical = CaptureOptions {
+            native_resolution: false,
+            ..Default::default()
+        };
+        assert_eq!(logical.to_dbus_map()["native-resolution"], Value::from(false));
+    }
+
+    #[test]
+    fn test_with_pipe_closes_on_error() {
+        let open_pipes = || {
+            std::fs::read_dir("/proc/self/fd")
+                .unwrap()
+                .filter_map(|entry| std::fs::read_link(entry.ok()?.path()).ok())
+                .collect::<Vec<_>>()
+        };
+        let mut pipe = None;
+        let result = block_on(with_pipe(|fd| {
+            // both ends link to the same pipe:[inode], which no other test can reuse
+            pipe = std::fs::read_link(format!("/proc/self/fd/{}", fd.as_raw_fd())).ok();
+            async { Err::<(), _>(anyhow!("call failed")) }
+        }));
+        assert!(result.is_err());
+
+        let pipe = pipe.unwrap();
+        // the read end goes with the reader thread, give it a moment to exit
+        let closed = (0..100).any(|_| {
+            thread::sleep(Duration::from_millis(10));
+            !open_pipes().contains(&pipe)
+        });
+        assert!(closed, "{} was leaked", pipe.display());
+    }
+
+    #[test]
+    fn test_with_pipe_aborts_on_error() {
+        // a write end left open elsewhere, as KWin may when the call fails
+        let mut kept = None;
+        let started = std::time::Instant::now();
+        let result = block_on(with_pipe(|fd| {
+            kept = fd.try_clone().ok();
+            async { Err::<(), _>(anyhow!("call failed")) }
+        }));
+        assert!(result.is_err());
+        assert!(started.elapsed() < Duration::from_secs(1));
+
+        // the reader is gone, writing fails instead of blocking it
+        let mut kept = File::from(kept.unwrap());
+        let gone = (0..100).any(|_| {
+            thread::sleep(Duration::from_millis(10));
+            std::io::Write::write(&mut kept, b"x").is_err()
+        });
+        assert!(gone, "the reader still waits for data");
+    }
+
+    #[test]
+    fn test_capture_inside_tokio() {
+        let runtime = tokio::runtime::Builder::new_current_thread()
+            .enable_all()
+            .build()
+            .unwrap();
+        // what the captures are made of, without taking any: an executor
+        // started inside tokio's would panic
+        runtime.block_on(async {
+            let _ = detect_backend().await;
+            assert_eq!(unblock(|| Ok(1)).await.unwrap(), 1);
+            let slow = with_timeout(future::pending::<()>(), Duration::from_millis(10), "nothing");
+            assert!(slow.await.is_err());
+        });
+    }
+
+    #[test]
+    fn test_with_pipe_reads() {
+        let (_, reading) = block_on(with_pipe(|fd| async move {
+            std::io::Write::write_all(&mut File::from(fd), b"image")?;
+            Ok(())
+        }))
+        .unwrap();
+        assert_eq!(block_on(reading.rx).unwrap().unwrap(), b"image");
+    }
+
+    #[test]
+    fn test_read_memfd() {
+        let memfd = memfd().unwrap();
+        let mut writer = memfd.try_clone().unwrap();
+        let written = thread::spawn(move || {
+            // starting later than the stall, which only counts once written to
+            for (delay, chunk) in [(150, &b"ima"[..]), (20, b"ge")] {
+                thread::sleep(Duration::from_millis(delay));
+                std::io::Write::write_all(&mut writer, chunk).unwrap();
+            }
+        });
+        // waits for the last chunk, though the first is readable before
+        let read = read_memfd(memfd, 5, Duration::from_millis(50));
+        assert_eq!(block_on(read).unwrap(), b"image");
+        written.join().unwrap();
+    }
+
+    #[test]
+    fn test_read_memfd_truncated() {
+        let memfd = memfd().unwrap();
+        std::io::Write::write_all(&mut memfd.try_clone().unwrap(), b"ima").unwrap();
+        let started = std::time::Instant::now();
+        // a 2x1 image needs 8 bytes, the writer stopped at 3
+        let buf = block_on(read_memfd(memfd, 8, Duration::from_millis(50))).unwrap();
+        assert!(started.elapsed() < Duration::from_secs(1));
+        let err = check_image_size(buf.len(), 2, 1, 8).unwrap_err().to_string();
+        assert!(err.contains("capture is truncated"), "{err}");
+    }
+
+    #[test]
+    fn test_stitch() {
+        let part = |width, height, value| RawCaptured {
+            width,
+            height,
+            scale: 1.,
+            buf: vec![value; (width * height * 4) as usize],
+        };
+        let target = |x, y, w, h| Region::from_xywh(x, y, w, h).unwrap();
+        let parts = vec![
+            (part(1, 2, 1), target(0, 0, 1, 2)),
+            // taken at twice the scale
+            (part(4, 4, 2), target(1, 0, 2, 2)),
+        ];
+        let stitched = stitch(3, 2, parts).unwrap();
+        let px = stitched.buf.chunks(4).map(|p| p[0]).collect::<Vec<_>>();
+        assert_eq!((stitched.width, stitched.height), (3, 2));
+        assert_eq!(px, [1, 2, 2, 1, 2, 2]);
+    }
+
+    #[test]
+    fn test_raw_captured_image() {
+        let captured = RawCaptured {
+            width: 2,
+            height: 1,
+            scale: 1.5,
+            buf: vec![0, 1, 2, 3, 4, 5, 6, 7],
+        };
+        assert!(!captured.is_empty());
+        assert_eq!(
+            format!("{captured:?}"),
+            "RawCaptured { width: 2, height: 1, scale: 1.5, bytes: 8 }"
+        );
+        let image = captured.as_rgba_image().unwrap();
+        assert_eq!(image.get_pixel(1, 0).0, [4, 5, 6, 7]);
+
+        let short = RawCaptured {
+            width: 3,
+            ..captured
+        };
+        assert!(short.into_rgba_image().is_none());
+        let empty = RawCaptured {
+            width: 0,
+            height: 0,
+            scale: 1.,
+            buf: Vec::new(),
+        };
+        assert!(empty.is_empty());
+    }
+
+    #[test]
+    fn test_reply_info() {
+        let reply = |entries: &[(&str, Value)]| {
+            entries
+                .iter()
+                .map(|(key, value)| (key.to_string(), value.try_to_owned().unwrap()))
+                .collect::<HashMap<_, _>>()
+        };
+        let info = ReplyInfo::parse(&reply(&[
+            ("width", Value::U32(3840)),
+            ("height", Value::U32(2160)),
+            ("stride", Value::U32(3840 * 4)),
+            ("scale", Value::F64(2.)),
+        ]));
+        assert_eq!(
+            info,
+            ReplyInfo {
+                format: QIMAGE_FORMAT_ARGB32,
+                width: 3840,
+                height: 2160,
+                stride: 3840 * 4,
+                scale: 2.,
+            }
+        );
+
+        // older KWin versions don't report a scale
+        let info = ReplyInfo::parse(&reply(&[("width", Value::U32(10))]));
+        assert_eq!((info.width, info.height, info.scale), (10, 0, 1.));
+        let info = ReplyInfo::parse(&reply(&[("scale", Value::F64(0.))]));
+        assert_eq!(info.scale, 1.);
+    }
+
+    #[test]
+    fn test_kwin_missing() {
+        let fdo = |err| zbus::Error::FDO(Box::new(err));
+        assert!(kwin_missing(&fdo(fdo::Error::ServiceUnknown("".into()))));
+        assert!(kwin_missing(&fdo(fdo::Error::UnknownInterface("".into()))));
+        assert!(kwin_missing(&zbus::Error::InterfaceNotFound));
+        assert!(!kwin_missing(&fdo(fdo::Error::AccessDenied("".into()))));
+        assert!(!kwin_missing(&zbus::Error::InvalidReply));
+    }
+
+    #[test]
+    fn test_crop_workspace() {
+        // 4x2 workspace at twice the scale, starting left of the primary output
+        let workspace = RawCaptured {
+            width: 8,
+            height: 4,
+            scale: 2.,
+            buf: (0..32u8).flat_map(|i| [i; 4]).collect(),
+        };
+        let px = |captured: &RawCaptured| captured.buf.chunks(4).map(|p| p[0]).collect::<Vec<_>>();
+
+        let region = Region::from_xywh(0, 1, 1, 1).unwrap();
+        let cropped = workspace.crop_workspace((-1, 0), region).unwrap();
+        assert_eq!((cropped.width, cropped.height, cropped.scale), (2, 2, 2.));
+        assert_eq!(px(&cropped), [18, 19, 26, 27]);
+
+        // clipped to the workspace
+        let region = Region::from_xywh(2, -1, 5, 2).unwrap();
+        let cropped = workspace.crop_workspace((-1, 0), region).unwrap();
+        assert_eq!((cropped.width, cropped.height), (2, 2));
+        assert_eq!(px(&cropped), [6, 7, 14, 15]);
+
+        let region = Region::from_xywh(10, 10, 1, 1).unwrap();
+        assert!(workspace.crop_workspace((0, 0), region).is_err());
+    }
+
+    #[test]
+    fn test_crop() {
+        // 3x2 image, each pixel filled with its index
+        let buf = (0..6u8).flat_map(|i| [i; 4]).collect::<Vec<_>>();
+        let px = |buf: &[u8]| buf.chunks(4).map(|p| p[0]).collect::<Vec<_>>();
+
+        let (out, w, h) = crop(&buf, 3, 2, 1, 0, 2, 2).unwrap();
+        assert_eq!((w, h), (2, 2));
+        assert_eq!(px(&out), [1, 2, 4, 5]);
+
+        // clipped to the image
+        let (out, w, h) = crop(&buf, 3, 2, -1, 1, 3, 5).unwrap();
+        assert_eq!((w, h), (2, 1));
+        assert_eq!(px(&out), [3, 4]);
+
+        assert!(crop(&buf, 3, 2, 3, 0, 1, 1).is_none());
+    }
+
     #[test]
     fn test_capture_screen() {
         block_on(async {
-            let captured = screen("DP-1").await;
+            let captured = screen("DP-1", None).await;
             match captured {
                 Ok(img) => {
                     let img: Option<ImageBuffer<Rgba<u8>, Vec<u8>>> =
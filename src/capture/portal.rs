@@ -0,0 +1,188 @@
+//! Capture through the `org.freedesktop.portal.Screenshot` desktop portal,
+//! for GNOME and sandboxed environments.
+//!
+//! The portal implementation may show a permission prompt on first use, or a
+//! dialog for every capture depending on the desktop. The screenshot is handed
+//! back as an image file, which is removed once read.
+use anyhow::{anyhow, bail, Result};
+use futures::StreamExt;
+use std::{
+    collections::HashMap,
+    ffi::OsString,
+    os::unix::ffi::OsStringExt,
+    fs,
+    path::PathBuf,
+    process,
+    time::{SystemTime, UNIX_EPOCH},
+};
+use zbus::{
+    proxy,
+    zvariant::{OwnedObjectPath, OwnedValue, Value},
+};
+
+use super::{session, RawCaptured};
+use crate::logger::{debug, warn};
+use crate::outputs;
+use crate::selection::{Cancelled, Region};
+
+#[proxy(
+    default_service = "org.freedesktop.portal.Desktop",
+    interface = "org.freedesktop.portal.Screenshot",
+    default_path = "/org/freedesktop/portal/desktop"
+)]
+trait Screenshot {
+    /// options:
+    ///     handle_token: s
+    ///     modal: b
+    ///     interactive: b
+    fn screenshot(
+        &self,
+        parent_window: &str,
+        options: HashMap<&str, &Value<'_>>,
+    ) -> zbus::Result<OwnedObjectPath>;
+
+    #[zbus(property)]
+    fn version(&self) -> zbus::Result<u32>;
+}
+
+#[proxy(
+    default_service = "org.freedesktop.portal.Desktop",
+    interface = "org.freedesktop.portal.Request"
+)]
+trait Request {
+    #[zbus(signal)]
+    fn response(&self, response: u32, results: HashMap<String, OwnedValue>) -> zbus::Result<()>;
+}
+
+/// Whether a desktop portal with the Screenshot interface is running.
+pub async fn available() -> bool {
+    async fn probe() -> Result<()> {
+        let conn = session().await?;
+        let proxy = ScreenshotProxy::new(&conn).await?;
+        proxy.version().await?;
+        Ok(())
+    }
+    probe().await.is_ok()
+}
+
+/// Take a screenshot of everything and return the path of the image file.
+async fn screenshot() -> Result<PathBuf> {
+    let conn = session().await?;
+
+    // subscribe to the response before asking, it may arrive before the reply
+    let token = format!(
+        "rq{}_{}",
+        process::id(),
+        SystemTime::now().duration_since(UNIX_EPOCH)?.subsec_nanos()
+    );
+    let sender = conn
+        .unique_name()
+        .ok_or(anyhow!("connection has no unique name"))?
+        .trim_start_matches(':')
+        .replace('.', "_");
+    let path = format!("/org/freedesktop/portal/desktop/request/{sender}/{token}");
+    let request = RequestProxy::builder(&conn).path(path)?.build().await?;
+    let mut responses = request.receive_response().await?;
+
+    let handle_token = Value::from(token.as_str());
+    let interactive = Value::from(false);
+    let options = HashMap::from([
+        ("handle_token", &handle_token),
+        ("interactive", &interactive),
+    ]);
+    let proxy = ScreenshotProxy::new(&conn).await?;
+    let handle = proxy.screenshot("", options).await?;
+    debug!("portal screenshot request {}", handle.as_str());
+
+    let response = responses
+        .next()
+        .await
+        .ok_or(anyhow!("portal closed the request without a response"))?;
+    let args = response.args()?;
+    match args.response {
+        0 => {}
+        1 => return Err(Cancelled.into()),
+        code => bail!("portal screenshot failed with response {code}"),
+    }
+    let uri = args
+        .results
+        .get("uri")
+        .and_then(|uri| uri.downcast_ref::<&str>().ok())
+        .ok_or(anyhow!("portal response has no uri"))?;
+    file_path(uri)
+}
+
+/// Capture everything on screen.
+pub async fn workspace() -> Result<RawCaptured> {
+    let path = screenshot().await?;
+    debug!("portal screenshot saved to {}", path.display());
+    let image = image::open(&path);
+    if let Err(err) = fs::remove_file(&path) {
+        warn!("failed to remove {}: {err}", path.display());
+    }
+    let image = image?.to_rgba8();
+    // the portal saves physical pixels, the workspace is laid out in logical ones
+    let scale = match outputs::workspace()? {
+        Some(workspace) => image.width() as f64 / workspace.width() as f64,
+        None => 1.,
+    };
+    Ok(RawCaptured {
+        width: image.width(),
+        height: image.height(),
+        scale,
+        buf: image.into_raw(),
+    })
+}
+
+/// Capture the logical area at `x`, `y` of size `w`x`h` out of a full
+/// screenshot.
+pub async fn area(x: i32, y: i32, w: u32, h: u32) -> Result<RawCaptured> {
+    let region = Region::from_xywh(x, y, w, h).ok_or(anyhow!("invalid region"))?;
+    let workspace = workspace().await?;
+    let origin = outputs::workspace()?.map_or((0, 0), |region| (region.x(), region.y()));
+    workspace.crop_workspace(origin, region)
+}
+
+/// Turn a `file://` uri into a path, undoing percent encoding.
+fn file_path(uri: &str) -> Result<PathBuf> {
+    let Some(encoded) = uri.strip_prefix("file://") else {
+        bail!("unsupported screenshot uri: {uri}");
+    };
+    let mut bytes = Vec::with_capacity(encoded.len());
+    let mut rest = encoded.as_bytes();
+    while let Some((&b, tail)) = rest.split_first() {
+        let hex = tail
+            .get(..2)
+            .and_then(|h| std::str::from_utf8(h).ok())
+            .and_then(|h| u8::from_str_radix(h, 16).ok());
+        match (b, hex) {
+            (b'%', Some(decoded)) => {
+                bytes.push(decoded);
+                rest = &tail[2..];
+            }
+            _ => {
+                bytes.push(b);
+                rest = tail;
+            }
+        }
+    }
+    Ok(PathBuf::from(OsString::from_vec(bytes)))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_file_path() {
+        assert_eq!(
+            file_path("file:///home/me/Pictures/Screenshot%20from%202024.png").unwrap(),
+            PathBuf::from("/home/me/Pictures/Screenshot from 2024.png")
+        );
+        assert_eq!(
+            file_path("file:///tmp/a%2").unwrap(),
+            PathBuf::from("/tmp/a%2")
+        );
+        assert!(file_path("https://example.com/a.png").is_err());
+    }
+}
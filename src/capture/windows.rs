@@ -0,0 +1,174 @@
+//! Where KWin's windows are, for `--window` to highlight the one under the
+//! pointer.
+//!
+//! KWin tells nobody over D-Bus, only its scripts see the windows. So a
+//! script is loaded for a moment to send their geometry back to us.
+use anyhow::{anyhow, Result};
+use futures::channel::oneshot;
+use serde::Deserialize;
+use std::{env, fs, process, time::Duration};
+use zbus::{interface, proxy};
+
+use super::{session, with_timeout};
+use crate::logger::{debug, warn};
+use crate::selection::Region;
+
+/// where the script reports to, on our own connection
+const PATH: &str = "/io/github/EvinceMoi/Rq/Windows";
+const INTERFACE: &str = "io.github.EvinceMoi.Rq.Windows";
+/// how long the script may take to report
+const SCRIPT_TIMEOUT: Duration = Duration::from_secs(2);
+
+/// Sends the visible windows on the current desktop, bottom to top, as
+/// `[[internalId, x, y, width, height], ...]`. KWin 5 names a few things
+/// differently.
+const SCRIPT: &str = r#"
+const onDesktop = (w) =>
+    w.onAllDesktops ||
+    (w.desktops ? w.desktops.includes(workspace.currentDesktop) : w.desktop === workspace.currentDesktop);
+const windows = (workspace.stackingOrder || workspace.clientList())
+    .filter((w) => (w.normalWindow || w.dialog) && !w.minimized && onDesktop(w))
+    .map((w) => {
+        const g = w.frameGeometry;
+        return [w.internalId.toString(), g.x, g.y, g.width, g.height];
+    });
+callDBus("{service}", "{path}", "{interface}", "Windows", JSON.stringify(windows));
+"#;
+
+/// A window KWin can capture.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Window {
+    /// what `capture::window` takes
+    pub handle: String,
+    /// in logical pixels, frame included
+    pub region: Region,
+}
+
+#[proxy(
+    default_service = "org.kde.KWin",
+    interface = "org.kde.kwin.Scripting",
+    default_path = "/Scripting"
+)]
+trait Scripting {
+    #[zbus(name = "loadScript")]
+    fn load_script(&self, file_path: &str, plugin_name: &str) -> zbus::Result<i32>;
+
+    #[zbus(name = "unloadScript")]
+    fn unload_script(&self, plugin_name: &str) -> zbus::Result<bool>;
+}
+
+#[proxy(default_service = "org.kde.KWin", interface = "org.kde.kwin.Script")]
+trait Script {
+    #[zbus(name = "run")]
+    fn run(&self) -> zbus::Result<()>;
+}
+
+/// Takes the report of the script.
+struct Report {
+    tx: Option<oneshot::Sender<String>>,
+}
+
+#[interface(name = "io.github.EvinceMoi.Rq.Windows")]
+impl Report {
+    fn windows(&mut self, windows: String) {
+        if let Some(tx) = self.tx.take() {
+            let _ = tx.send(windows);
+        }
+    }
+}
+
+/// The windows on the current desktop, topmost last.
+pub async fn list() -> Result<Vec<Window>> {
+    let conn = session().await?;
+    let service = conn
+        .unique_name()
+        .ok_or(anyhow!("connection has no unique name"))?
+        .to_string();
+
+    let (tx, rx) = oneshot::channel();
+    conn.object_server().at(PATH, Report { tx: Some(tx) }).await?;
+    let plugin = format!("rq-windows-{}", process::id());
+    let path = env::temp_dir().join(format!("{plugin}.js"));
+    let script = SCRIPT
+        .replace("{service}", &service)
+        .replace("{path}", PATH)
+        .replace("{interface}", INTERFACE);
+    fs::write(&path, script)?;
+
+    let scripting = ScriptingProxy::new(&conn).await?;
+    let reported = async {
+        let id = scripting
+            .load_script(&path.to_string_lossy(), &plugin)
+            .await?;
+        // KWin 6 puts the script under /Scripting, KWin 5 at the root
+        let mut ran = Err(anyhow!("KWin did not load the script"));
+        for script in [format!("/Scripting/Script{id}"), format!("/{id}")] {
+            let proxy = ScriptProxy::builder(&conn).path(script)?.build().await?;
+            ran = proxy.run().await.map_err(Into::into);
+            if ran.is_ok() {
+                break;
+            }
+        }
+        ran?;
+        with_timeout(rx, SCRIPT_TIMEOUT, "KWin's windows")
+            .await?
+            .map_err(|_| anyhow!("the window list was dropped"))
+    }
+    .await;
+
+    // clean up whatever happened above
+    if let Err(err) = scripting.unload_script(&plugin).await {
+        warn!("failed to unload the KWin script {plugin}: {err}");
+    }
+    let _ = fs::remove_file(&path);
+    conn.object_server().remove::<Report, _>(PATH).await?;
+
+    let windows = parse(&reported?)?;
+    debug!("KWin has {} windows on the current desktop", windows.len());
+    Ok(windows)
+}
+
+/// The windows in the report of the script, those without a size left out.
+fn parse(report: &str) -> Result<Vec<Window>> {
+    #[derive(Deserialize)]
+    struct Reported(String, f64, f64, f64, f64);
+
+    let reported = serde_json::from_str::<Vec<Reported>>(report)?;
+    Ok(reported
+        .into_iter()
+        .filter_map(|Reported(handle, x, y, w, h)| {
+            let region = Region::from_xywh(
+                x.round() as i32,
+                y.round() as i32,
+                w.round() as u32,
+                h.round() as u32,
+            )?;
+            Some(Window { handle, region })
+        })
+        .collect())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_parse() {
+        let report = r#"[["{a}", 0, 0, 800, 600], ["{b}", -10.4, 20, 300.2, 200], ["{c}", 5, 5, 0, 0]]"#;
+        let windows = parse(report).unwrap();
+        assert_eq!(
+            windows,
+            [
+                Window {
+                    handle: "{a}".into(),
+                    region: Region::from_xywh(0, 0, 800, 600).unwrap(),
+                },
+                Window {
+                    handle: "{b}".into(),
+                    region: Region::from_xywh(-10, 20, 300, 200).unwrap(),
+                },
+            ]
+        );
+        assert!(parse("{}").is_err());
+    }
+}
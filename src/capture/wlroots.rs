@@ -0,0 +1,305 @@
+//! Capture through the wlroots `zwlr_screencopy_manager_v1` protocol, used by
+//! Sway, Hyprland and other wlroots based compositors.
+use anyhow::{anyhow, bail, Result};
+use image::{imageops, RgbaImage};
+use smithay_client_toolkit::{
+    delegate_output, delegate_registry, delegate_shm,
+    output::{OutputHandler, OutputState},
+    registry::{ProvidesRegistryState, RegistryState},
+    registry_handlers,
+    shm::{raw::RawPool, Shm, ShmHandler},
+};
+use wayland_client::{
+    delegate_noop,
+    globals::registry_queue_init,
+    protocol::{
+        wl_buffer::WlBuffer,
+        wl_output::{Transform, WlOutput},
+        wl_shm,
+    },
+    Connection, Dispatch, Proxy, QueueHandle, WEnum,
+};
+use wayland_protocols_wlr::screencopy::v1::client::{
+    zwlr_screencopy_frame_v1::{self, ZwlrScreencopyFrameV1},
+    zwlr_screencopy_manager_v1::ZwlrScreencopyManagerV1,
+};
+
+use super::{crop, repack, PixelFormat, RawCaptured};
+use crate::selection::{output_scale, Region};
+
+#[derive(Debug, Clone, Copy)]
+struct BufferInfo {
+    format: wl_shm::Format,
+    width: u32,
+    height: u32,
+    stride: u32,
+}
+
+#[derive(Default)]
+struct Frame {
+    info: Option<BufferInfo>,
+    pool: Option<RawPool>,
+    buffer: Option<WlBuffer>,
+    y_invert: bool,
+    ready: bool,
+    failed: bool,
+}
+
+struct ScreencopyState {
+    registry_state: RegistryState,
+    output_state: OutputState,
+    shm: Shm,
+    frame: Frame,
+}
+
+/// Whether the compositor offers wlroots screencopy.
+pub fn available() -> bool {
+    let Ok(conn) = Connection::connect_to_env() else {
+        return false;
+    };
+    let Ok((globals, _)) = registry_queue_init::<ScreencopyState>(&conn) else {
+        return false;
+    };
+    globals.contents().with_list(|list| {
+        list.iter()
+            .any(|g| g.interface == ZwlrScreencopyManagerV1::interface().name)
+    })
+}
+
+/// Capture the area at `x`, `y` of size `w`x`h`.
+///
+/// Coordinates are in physical pixels of the output containing `x`, `y`, as
+/// produced by `selection`. Only that output is copied, the area is cropped to it.
+/// The cursor is drawn in when `include_cursor`.
+pub fn area(x: i32, y: i32, w: u32, h: u32, include_cursor: bool) -> Result<RawCaptured> {
+    let conn = Connection::connect_to_env()?;
+    let (globals, mut event_queue) = registry_queue_init::<ScreencopyState>(&conn)?;
+    let qh = event_queue.handle();
+
+    let manager: ZwlrScreencopyManagerV1 = globals
+        .bind(&qh, 1..=3, ())
+        .map_err(|_| anyhow!("compositor does not support wlr-screencopy"))?;
+    let mut state = ScreencopyState {
+        registry_state: RegistryState::new(&globals),
+        output_state: OutputState::new(&globals, &qh),
+        shm: Shm::bind(&globals, &qh)?,
+        frame: Frame::default(),
+    };
+    // outputs, then their xdg-output geometry
+    event_queue.roundtrip(&mut state)?;
+    event_queue.roundtrip(&mut state)?;
+
+    let (output, origin, transform) = state
+        .output_state
+        .outputs()
+        .find_map(|output| {
+            let info = state.output_state.info(&output)?;
+            let (lx, ly) = info.logical_position?;
+            let (lw, lh) = info.logical_size?;
+            let scale = output_scale(&info);
+            let scaled = |v: i32| (v as f64 * scale).round() as i32;
+            let physical =
+                Region::from_xywh(scaled(lx), scaled(ly), scaled(lw) as u32, scaled(lh) as u32)?;
+            let contains = (physical.left()..physical.right()).contains(&x)
+                && (physical.top()..physical.bottom()).contains(&y);
+            contains.then_some((output, (physical.x(), physical.y()), info.transform))
+        })
+        .ok_or(anyhow!("region at {x},{y} is outside of all outputs"))?;
+
+    let frame = manager.capture_output(include_cursor.into(), &output, &qh, ());
+    while !state.frame.ready && !state.frame.failed {
+        event_queue.blocking_dispatch(&mut state)?;
+    }
+    frame.destroy();
+    if let Some(buffer) = state.frame.buffer.take() {
+        buffer.destroy();
+    }
+    if state.frame.failed {
+        bail!("compositor failed to copy the output");
+    }
+
+    let info = state.frame.info.expect("frame is ready without a buffer");
+    let pool = state
+        .frame
+        .pool
+        .as_mut()
+        .expect("frame is ready without a pool");
+    let format = PixelFormat::from_wl_shm(info.format)?;
+    let size = (info.stride * info.height) as usize;
+    let mut data = pool.mmap()[..size].to_vec();
+    if state.frame.y_invert {
+        data = data
+            .chunks(info.stride as usize)
+            .rev()
+            .flatten()
+            .copied()
+            .collect();
+    }
+    let buf = repack(&data, info.width, info.height, info.stride, format);
+    let (buf, width, height) = upright(buf, info.width, info.height, transform)
+        .ok_or(anyhow!("captured buffer does not match its size"))?;
+
+    let (left, top) = (x - origin.0, y - origin.1);
+    let (buf, width, height) = crop(&buf, width, height, left, top, w, h)
+        .ok_or(anyhow!("region does not overlap the captured output"))?;
+    Ok(RawCaptured {
+        width,
+        height,
+        scale: 1.,
+        buf,
+    })
+}
+
+/// Turn a copy of an output, which comes as the output's framebuffer, the way
+/// it is shown by applying the output `transform`.
+fn upright(
+    buf: Vec<u8>,
+    width: u32,
+    height: u32,
+    transform: Transform,
+) -> Option<(Vec<u8>, u32, u32)> {
+    let image = RgbaImage::from_vec(width, height, buf)?;
+    let image = match transform {
+        Transform::Flipped
+        | Transform::Flipped90
+        | Transform::Flipped180
+        | Transform::Flipped270 => imageops::flip_horizontal(&image),
+        _ => image,
+    };
+    let image = match transform {
+        Transform::_90 | Transform::Flipped90 => imageops::rotate90(&image),
+        Transform::_180 | Transform::Flipped180 => imageops::rotate180(&image),
+        Transform::_270 | Transform::Flipped270 => imageops::rotate270(&image),
+        _ => image,
+    };
+    let (width, height) = image.dimensions();
+    Some((image.into_raw(), width, height))
+}
+
+impl ScreencopyState {
+    fn copy(&mut self, frame: &ZwlrScreencopyFrameV1, qh: &QueueHandle<Self>) {
+        let Some(info) = self.frame.info else {
+            self.frame.failed = true;
+            return;
+        };
+        let size = (info.stride * info.height) as usize;
+        let Ok(mut pool) = RawPool::new(size, &self.shm) else {
+            self.frame.failed = true;
+            return;
+        };
+        let buffer = pool.create_buffer(
+            0,
+            info.width as i32,
+            info.height as i32,
+            info.stride as i32,
+            info.format,
+            (),
+            qh,
+        );
+        frame.copy(&buffer);
+        self.frame.pool = Some(pool);
+        self.frame.buffer = Some(buffer);
+    }
+}
+
+impl Dispatch<ZwlrScreencopyFrameV1, ()> for ScreencopyState {
+    fn event(
+        state: &mut Self,
+        frame: &ZwlrScreencopyFrameV1,
+        event: zwlr_screencopy_frame_v1::Event,
+        _data: &(),
+        _conn: &Connection,
+        qh: &QueueHandle<Self>,
+    ) {
+        use zwlr_screencopy_frame_v1::Event::*;
+        match event {
+            Buffer {
+                format: WEnum::Value(format),
+                width,
+                height,
+                stride,
+            } => {
+                // take the first shm format we understand
+                if state.frame.info.is_none() && PixelFormat::from_wl_shm(format).is_ok() {
+                    state.frame.info = Some(BufferInfo {
+                        format,
+                        width,
+                        height,
+                        stride,
+                    });
+                }
+                // before version 3 there is no buffer_done
+                if frame.version() < 3 {
+                    state.copy(frame, qh);
+                }
+            }
+            BufferDone => state.copy(frame, qh),
+            Flags { flags } => {
+                state.frame.y_invert = matches!(
+                    flags,
+                    WEnum::Value(flags) if flags.contains(zwlr_screencopy_frame_v1::Flags::YInvert)
+                );
+            }
+            Ready { .. } => state.frame.ready = true,
+            Failed => state.frame.failed = true,
+            _ => {}
+        }
+    }
+}
+
+delegate_noop!(ScreencopyState: ZwlrScreencopyManagerV1);
+delegate_noop!(ScreencopyState: ignore WlBuffer);
+
+delegate_registry!(ScreencopyState);
+impl ProvidesRegistryState for ScreencopyState {
+    fn registry(&mut self) -> &mut RegistryState {
+        &mut self.registry_state
+    }
+    registry_handlers![OutputState];
+}
+
+delegate_output!(ScreencopyState);
+impl OutputHandler for ScreencopyState {
+    fn output_state(&mut self) -> &mut OutputState {
+        &mut self.output_state
+    }
+
+    fn new_output(&mut self, _conn: &Connection, _qh: &QueueHandle<Self>, _output: WlOutput) {}
+
+    fn update_output(&mut self, _conn: &Connection, _qh: &QueueHandle<Self>, _output: WlOutput) {}
+
+    fn output_destroyed(&mut self, _conn: &Connection, _qh: &QueueHandle<Self>, _output: WlOutput) {
+    }
+}
+
+delegate_shm!(ScreencopyState);
+impl ShmHandler for ScreencopyState {
+    fn shm_state(&mut self) -> &mut Shm {
+        &mut self.shm
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_upright() {
+        // 2x1 framebuffer, each pixel filled with its index
+        let buf = (0..2u8).flat_map(|i| [i; 4]).collect::<Vec<_>>();
+        let px = |buf: &[u8]| buf.chunks(4).map(|p| p[0]).collect::<Vec<_>>();
+
+        let (out, w, h) = upright(buf.clone(), 2, 1, Transform::Normal).unwrap();
+        assert_eq!((px(&out), w, h), (vec![0, 1], 2, 1));
+        let (out, w, h) = upright(buf.clone(), 2, 1, Transform::_90).unwrap();
+        assert_eq!((px(&out), w, h), (vec![0, 1], 1, 2));
+        let (out, w, h) = upright(buf.clone(), 2, 1, Transform::_180).unwrap();
+        assert_eq!((px(&out), w, h), (vec![1, 0], 2, 1));
+        let (out, w, h) = upright(buf.clone(), 2, 1, Transform::_270).unwrap();
+        assert_eq!((px(&out), w, h), (vec![1, 0], 1, 2));
+        let (out, w, h) = upright(buf.clone(), 2, 1, Transform::Flipped).unwrap();
+        assert_eq!((px(&out), w, h), (vec![1, 0], 2, 1));
+
+        assert!(upright(buf, 3, 1, Transform::Normal).is_none());
+    }
+}
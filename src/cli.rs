@@ -0,0 +1,568 @@
+use clap::{builder::ArgPredicate, ArgAction, ArgGroup, Parser, ValueEnum};
+use log::LevelFilter;
+use std::path::PathBuf;
+use tiny_skia::Color;
+
+use crate::capture;
+use crate::decode::{Format, Invert};
+use crate::selection::{Aspect, OverlayKeyboard, Region};
+
+/// Select a region of the screen and decode the QR codes in it.
+///
+/// Drag to select, or click to take a whole output. Adjust with the arrow
+/// keys and press Enter to confirm. Escape or a right click cancels.
+///
+/// Press c to type the region as x,y,w,h instead, Enter shows it selected.
+/// Press d to turn the dimming off and on, to see dark content better.
+///
+/// Defaults for the options can be set in $XDG_CONFIG_HOME/rq/config.toml,
+/// e.g. `fps = 60` or `two-click = true`.
+///
+/// Exits with 0 when a code was decoded, 1 on errors, 2 on invalid arguments,
+/// 3 when no code was found or nothing was selected before --timeout, and 4
+/// when the selection was cancelled.
+#[derive(Parser, Debug)]
+#[command(version, about)]
+// what needs the frozen screen asks for either, --pick-color implies --freeze
+#[command(group(ArgGroup::new("frozen").args(["freeze", "pick_color"]).multiple(true)))]
+// captures of a window clicked on
+#[command(group(ArgGroup::new("picks_window").args(["kwin_interactive", "window"]).multiple(true)))]
+// captures that need nobody to select anything
+#[command(group(ArgGroup::new("unattended").args(["region", "monitor", "active_screen", "workspace"]).multiple(true)))]
+pub struct Args {
+    /// Log more, repeat for even more: -v info, -vv debug, -vvv trace
+    ///
+    /// Takes precedence over RUST_LOG.
+    #[arg(short, long, action = ArgAction::Count)]
+    pub verbose: u8,
+
+    /// Only log errors, or with --log-file nothing to the terminal
+    ///
+    /// Takes precedence over RUST_LOG. With --log-file, the file still gets
+    /// every record it would get without --quiet.
+    #[arg(short, long, conflicts_with = "verbose")]
+    pub quiet: bool,
+
+    /// Also append log records to FILE, handy when not started from a
+    /// terminal. With --quiet they go to FILE only
+    #[arg(long, value_name = "FILE")]
+    pub log_file: Option<PathBuf>,
+
+    /// Select by clicking two opposite corners instead of press-drag-release
+    #[arg(long)]
+    pub two_click: bool,
+
+    /// Color of the overlay dimming the screen outside the selection
+    #[arg(long, value_name = "RRGGBBAA", default_value = "64646480", value_parser = parse_color)]
+    pub dim_color: Color,
+
+    /// Color of the guide lines following the pointer before selecting
+    #[arg(long, value_name = "RRGGBBAA", default_value = "ffffffa0", value_parser = parse_color)]
+    pub guide_color: Color,
+
+    /// Color of the outline around the selection
+    #[arg(long, value_name = "RRGGBBAA", default_value = "ffffffff", value_parser = parse_color)]
+    pub border_color: Color,
+
+    /// Width of the outline around the selection, 0 to hide it
+    #[arg(long, value_name = "PX", default_value_t = 1, value_parser = clap::value_parser!(u32).range(0..=32))]
+    pub border_width: u32,
+
+    /// Redraw the overlay at most FPS times per second, as often as the
+    /// compositor asks when not given
+    #[arg(long, value_name = "FPS", value_parser = clap::value_parser!(u32).range(1..=1000))]
+    pub fps: Option<u32>,
+
+    /// Give up when nothing is selected within SECS seconds
+    #[arg(long, value_name = "SECS", value_parser = clap::value_parser!(u64).range(1..))]
+    pub timeout: Option<u64>,
+
+    /// How the overlay takes keyboard focus, for Escape, Enter and the arrows
+    ///
+    /// on-demand suits most compositors. exclusive is for those that never
+    /// focus on-demand layers, which came with version 4 of the layer-shell
+    /// protocol, e.g. ones built on wlroots older than 0.15. none leaves the
+    /// keyboard to the focused window, cancel with a right click then.
+    #[arg(long, value_name = "MODE", default_value = "on-demand")]
+    pub overlay_keyboard: OverlayKeyboard,
+
+    /// Wait SECS seconds before showing the overlay or capturing
+    #[arg(long, value_name = "SECS")]
+    pub delay: Option<u64>,
+
+    /// Capture the whole screen first and select on that still image
+    ///
+    /// Useful when the content underneath moves, at the cost of an extra capture.
+    #[arg(
+        long,
+        conflicts_with = "region",
+        default_value_if("pick_color", ArgPredicate::IsPresent, "true")
+    )]
+    pub freeze: bool,
+
+    /// Keep the selection at a width to height ratio, e.g. 1:1 for a square
+    #[arg(long, value_name = "W:H", value_parser = parse_aspect)]
+    pub aspect: Option<Aspect>,
+
+    /// Show the frozen screen magnified next to the pointer while selecting
+    #[arg(long, requires = "frozen")]
+    pub magnifier: bool,
+
+    /// Print the name, position, size, scale and transform of every output
+    #[arg(long, conflicts_with_all = ["file", "monitor", "active_screen", "region", "last", "freeze", "two_click"])]
+    pub list_outputs: bool,
+
+    /// Capture the whole output NAME instead of selecting, e.g. DP-1
+    #[arg(long, value_name = "NAME", conflicts_with_all = ["two_click", "freeze", "region"])]
+    pub monitor: Option<String>,
+
+    /// Let KWin ask what to capture instead of showing rq's own overlay
+    #[arg(
+        long,
+        value_name = "KIND",
+        num_args = 0..=1,
+        default_missing_value = "screen",
+        conflicts_with_all = ["two_click", "freeze", "monitor", "region"]
+    )]
+    pub kwin_interactive: Option<Interactive>,
+
+    /// Click a window to capture it, the one under the pointer is highlighted
+    ///
+    /// Only KWin tells where windows are, elsewhere the output under the
+    /// pointer is highlighted and captured instead.
+    #[arg(long, conflicts_with_all = ["freeze", "monitor", "active_screen", "workspace", "kwin_interactive", "primary", "region", "last", "watch", "pick_color", "frames", "file"])]
+    pub window: bool,
+
+    /// Keep the titlebar and borders the compositor draws around a window
+    ///
+    /// Only for `--window` and `--kwin-interactive window`, the capture is
+    /// the window's own content otherwise.
+    #[arg(long, requires = "picks_window")]
+    pub decoration: bool,
+
+    /// Capture the whole output with the pointer or focus instead of selecting
+    #[arg(long, conflicts_with_all = ["two_click", "freeze", "monitor", "kwin_interactive", "region"])]
+    pub active_screen: bool,
+
+    /// Capture the output the user is on: the active one with KWin, the only
+    /// one when there is a single output, or else select as usual
+    #[arg(long, conflicts_with_all = ["monitor", "active_screen", "workspace", "kwin_interactive", "region", "last", "watch", "pick_color", "file"])]
+    pub primary: bool,
+
+    /// Capture every output at once and decode all the codes anywhere on them
+    ///
+    /// Not supported with wlroots.
+    #[arg(long, conflicts_with_all = ["two_click", "freeze", "monitor", "active_screen", "kwin_interactive", "region", "last", "file", "watch"])]
+    pub workspace: bool,
+
+    /// Capture the given region instead of selecting one, e.g. 100,200,640,480
+    #[arg(long, value_name = "X,Y,W,H", value_parser = parse_region)]
+    pub region: Option<Region>,
+
+    /// Capture the region selected last time instead of selecting one
+    ///
+    /// Falls back to selecting when there is none or it is no longer on screen.
+    #[arg(long, conflicts_with_all = ["monitor", "active_screen", "kwin_interactive", "region"])]
+    pub last: bool,
+
+    /// Keep selecting and decoding regions until Escape is pressed
+    ///
+    /// Exits with 3 when none of them decoded.
+    #[arg(long, conflicts_with_all = ["monitor", "active_screen", "kwin_interactive", "region", "last", "file", "copy"])]
+    pub watch: bool,
+
+    /// Click a pixel and print its color as #rrggbbaa instead of decoding
+    ///
+    /// Reads the frozen screen, implies --freeze. With --json prints
+    /// {"color": "#rrggbbaa"}, with --copy copies it.
+    #[arg(long, conflicts_with_all = ["monitor", "active_screen", "workspace", "kwin_interactive", "region", "last", "file", "watch", "two_click", "aspect", "notify", "output", "output_dir"])]
+    pub pick_color: bool,
+
+    /// Capture and decode N times, with --region, --monitor, --active-screen
+    /// or --workspace
+    ///
+    /// Every line printed starts with the iteration, from 1, and the time of
+    /// the capture, separated by tabs, unless --raw. With --json each
+    /// iteration prints {"iteration": 1, "timestamp": "...", "results": [...]}
+    /// on a line.
+    /// Exits with 3 when none of them decoded.
+    #[arg(
+        long,
+        value_name = "N",
+        value_parser = clap::value_parser!(u32).range(1..),
+        requires = "unattended",
+        conflicts_with_all = ["watch", "copy", "kwin_interactive"]
+    )]
+    pub count: Option<u32>,
+
+    /// Capture the selection N times in a row and decode the codes found in
+    /// any of the captures, for codes in videos or animations
+    ///
+    /// Every code is printed once. With --output, the first capture is saved.
+    #[arg(
+        long,
+        value_name = "N",
+        value_parser = clap::value_parser!(u32).range(1..=100),
+        conflicts_with_all = ["monitor", "active_screen", "workspace", "kwin_interactive", "freeze", "watch", "count", "pick_color", "file", "output_dir"]
+    )]
+    pub frames: Option<u32>,
+
+    /// Stay running on the session bus and scan whenever `rq --client` asks
+    ///
+    /// The other options apply to every scan, the codes decoded go back to
+    /// the client instead of being printed.
+    #[arg(
+        long,
+        conflicts_with_all = ["client", "delay", "watch", "count", "frames", "pick_color", "stdout_ppm", "file", "copy", "notify", "list_outputs"]
+    )]
+    pub serve: bool,
+
+    /// Scan through the running `rq --serve`, or right here when there is none
+    ///
+    /// The codes decoded are reported as a plain run would, --json and
+    /// --raw included.
+    #[arg(
+        long,
+        conflicts_with_all = ["watch", "count", "frames", "pick_color", "stdout_ppm", "file", "list_outputs"]
+    )]
+    pub client: bool,
+
+    /// Start the captures of --count SECS seconds apart
+    #[arg(long, value_name = "SECS", default_value_t = 1, requires = "count")]
+    pub interval: u64,
+
+    /// Decode an image file instead of capturing the screen
+    #[arg(long, value_name = "PATH", conflicts_with_all = ["two_click", "freeze", "monitor", "active_screen", "kwin_interactive", "region", "last", "output_dir"])]
+    pub file: Option<PathBuf>,
+
+    /// Include the mouse cursor in captures, it is left out by default
+    ///
+    /// Not supported by the desktop portal.
+    #[arg(long, conflicts_with = "file")]
+    pub cursor: bool,
+
+    /// Capture in logical pixels, as the overlay shows them, instead of at
+    /// the outputs' native resolution
+    ///
+    /// Only KWin can capture in logical pixels.
+    #[arg(long, conflicts_with = "file")]
+    pub logical: bool,
+
+    /// Have KWin hand captures over in shared memory rather than a pipe,
+    /// faster for large captures
+    ///
+    /// Falls back to the pipe where memory files can't be created.
+    #[arg(long, conflicts_with = "file")]
+    pub memfd: bool,
+
+    /// Don't upscale small captures before decoding
+    #[arg(long)]
+    pub no_upscale: bool,
+
+    /// Kind of code to look for, auto looks for all of them
+    #[arg(long, value_name = "FORMAT", default_value = "qr")]
+    pub format: CodeFormat,
+
+    /// Look for light codes on a dark background: auto retries with the
+    /// capture inverted when nothing is found
+    #[arg(long, value_name = "WHEN", default_value = "auto")]
+    pub invert: Invert,
+
+    /// Print the results as JSON: {"results": [{"text": "...", "format": "qr",
+    /// "bounds": {"x", "y", "width", "height"}}]}, the format is one of qr,
+    /// ean13, code128 and datamatrix and the bounds are in pixels of the
+    /// capture.
+    ///
+    /// WiFi codes also get "wifi": {"ssid", "password", "security", "hidden"},
+    /// vCard and MECARD codes "contact": {"name", "phones", "emails", ...}.
+    ///
+    /// With --list-outputs: {"outputs": [{"name": "...", "x": 0, ...}]}
+    #[arg(long)]
+    pub json: bool,
+
+    /// Print only the decoded text, one code per line, without describing
+    /// WiFi codes or contacts. Logs always go to stderr
+    #[arg(long, conflicts_with = "json")]
+    pub raw: bool,
+
+    /// Show the result as a desktop notification
+    #[arg(long)]
+    pub notify: bool,
+
+    /// Copy the first decoded payload to the clipboard
+    ///
+    /// rq keeps running in the background until something else is copied.
+    #[arg(long)]
+    pub copy: bool,
+
+    /// Save the capture to PATH as png, jpeg or webp, going by the extension
+    #[arg(short, long, value_name = "PATH", conflicts_with = "file")]
+    pub output: Option<PathBuf>,
+
+    /// Save every capture into DIR under a unique timestamped name
+    #[arg(long, value_name = "DIR")]
+    pub output_dir: Option<PathBuf>,
+
+    /// Write the capture to stdout as a binary PPM instead of decoding it,
+    /// e.g. to pipe it into ImageMagick
+    ///
+    /// The bytes are the ASCII header "P6\n<width> <height>\n255\n", then
+    /// red, green and blue for every pixel, one byte each, row by row from
+    /// the top-left. Transparency is dropped. Logs still go to stderr.
+    #[arg(
+        long,
+        conflicts_with_all = ["json", "raw", "notify", "copy", "output", "output_dir", "watch", "count", "frames", "pick_color", "file", "list_outputs"]
+    )]
+    pub stdout_ppm: bool,
+
+    /// Append the (sanitized) first decoded payload to names in --output-dir
+    #[arg(long, requires = "output_dir")]
+    pub name_with_payload: bool,
+}
+
+impl Args {
+    /// The log level asked for with -v or -q, if any.
+    pub fn log_level(&self) -> Option<LevelFilter> {
+        if self.quiet {
+            return Some(LevelFilter::Error);
+        }
+        match self.verbose {
+            0 => None,
+            1 => Some(LevelFilter::Info),
+            2 => Some(LevelFilter::Debug),
+            _ => Some(LevelFilter::Trace),
+        }
+    }
+}
+
+/// What `--kwin-interactive` lets the user click.
+#[derive(ValueEnum, Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Interactive {
+    Window,
+    Screen,
+}
+impl Interactive {
+    /// The `kind` of KWin's `CaptureInteractive`.
+    pub fn kind(self) -> u32 {
+        match self {
+            Self::Window => capture::INTERACTIVE_WINDOW,
+            Self::Screen => capture::INTERACTIVE_SCREEN,
+        }
+    }
+}
+
+/// What `--format` looks for.
+#[derive(ValueEnum, Debug, Clone, Copy, PartialEq, Eq)]
+pub enum CodeFormat {
+    Qr,
+    #[value(alias = "ean")]
+    Ean13,
+    Code128,
+    Datamatrix,
+    Auto,
+}
+impl CodeFormat {
+    pub fn formats(self) -> Vec<Format> {
+        match self {
+            Self::Qr => vec![Format::Qr],
+            Self::Ean13 => vec![Format::Ean13],
+            Self::Code128 => vec![Format::Code128],
+            Self::Datamatrix => vec![Format::DataMatrix],
+            Self::Auto => Format::ALL.to_vec(),
+        }
+    }
+}
+
+fn parse_region(s: &str) -> Result<Region, String> {
+    let parts = s.split(',').map(str::trim).collect::<Vec<_>>();
+    let [x, y, w, h] = parts[..] else {
+        return Err("expected four comma separated numbers: X,Y,W,H".into());
+    };
+    let x = x.parse::<i32>().map_err(|e| format!("invalid x `{x}`: {e}"))?;
+    let y = y.parse::<i32>().map_err(|e| format!("invalid y `{y}`: {e}"))?;
+    let w = w.parse::<u32>().map_err(|e| format!("invalid width `{w}`: {e}"))?;
+    let h = h.parse::<u32>().map_err(|e| format!("invalid height `{h}`: {e}"))?;
+    Region::from_xywh(x, y, w, h).ok_or_else(|| "region must have a non-zero size".into())
+}
+
+fn parse_aspect(s: &str) -> Result<Aspect, String> {
+    let Some((w, h)) = s.split_once(':') else {
+        return Err("expected a ratio of two numbers: W:H".into());
+    };
+    let side = |v: &str| match v.trim().parse::<u32>() {
+        Ok(0) => Err("ratio sides must not be zero".to_owned()),
+        Ok(v) => Ok(v),
+        Err(e) => Err(format!("invalid ratio side `{v}`: {e}")),
+    };
+    Ok(Aspect {
+        width: side(w)?,
+        height: side(h)?,
+    })
+}
+
+fn parse_color(s: &str) -> Result<Color, String> {
+    let hex = s.trim_start_matches('#');
+    if hex.len() != 8 || !hex.bytes().all(|b| b.is_ascii_hexdigit()) {
+        return Err("expected eight hex digits: RRGGBBAA".into());
+    }
+    let channel = |i: usize| u8::from_str_radix(&hex[i..i + 2], 16).unwrap();
+    Ok(Color::from_rgba8(channel(0), channel(2), channel(4), channel(6)))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_parse_region() {
+        assert_eq!(
+            parse_region("100,200,640,480"),
+            Ok(Region::from_xywh(100, 200, 640, 480).unwrap())
+        );
+        assert_eq!(
+            parse_region("-10, 0, 5, 5"),
+            Ok(Region::from_xywh(-10, 0, 5, 5).unwrap())
+        );
+        assert!(parse_region("1,2,3").is_err());
+        assert!(parse_region("1,2,3,4,5").is_err());
+        assert!(parse_region("a,2,3,4").is_err());
+        assert!(parse_region("1,2,-3,4").is_err());
+        assert!(parse_region("1,2,0,4").is_err());
+    }
+
+    #[test]
+    fn test_log_level() {
+        let parse = |args: &[&str]| Args::try_parse_from(args).map(|args| args.log_level());
+        assert_eq!(parse(&["rq"]).unwrap(), None);
+        assert_eq!(parse(&["rq", "-v"]).unwrap(), Some(LevelFilter::Info));
+        assert_eq!(parse(&["rq", "-vv"]).unwrap(), Some(LevelFilter::Debug));
+        assert_eq!(parse(&["rq", "-v", "-v", "-v", "-v"]).unwrap(), Some(LevelFilter::Trace));
+        assert_eq!(parse(&["rq", "-q"]).unwrap(), Some(LevelFilter::Error));
+        assert!(parse(&["rq", "-q", "-v"]).is_err());
+    }
+
+    #[test]
+    fn test_overlay_keyboard() {
+        let parse = |args: &[&str]| Args::try_parse_from(args).map(|args| args.overlay_keyboard);
+        assert_eq!(parse(&["rq"]).unwrap(), OverlayKeyboard::OnDemand);
+        assert_eq!(
+            parse(&["rq", "--overlay-keyboard", "exclusive"]).unwrap(),
+            OverlayKeyboard::Exclusive
+        );
+        assert_eq!(
+            parse(&["rq", "--overlay-keyboard", "none"]).unwrap(),
+            OverlayKeyboard::None
+        );
+        assert!(parse(&["rq", "--overlay-keyboard", "off"]).is_err());
+    }
+
+    #[test]
+    fn test_pick_color() {
+        let args = Args::try_parse_from(["rq", "--pick-color"]).unwrap();
+        assert!(args.pick_color && args.freeze);
+        assert!(!Args::try_parse_from(["rq"]).unwrap().freeze);
+        // implied, so the options needing it work too
+        assert!(Args::try_parse_from(["rq", "--pick-color", "--magnifier"]).is_ok());
+        assert!(Args::try_parse_from(["rq", "--magnifier"]).is_err());
+        assert!(Args::try_parse_from(["rq", "--pick-color", "--watch"]).is_err());
+    }
+
+    #[test]
+    fn test_count() {
+        let parse = |args: &[&str]| Args::try_parse_from(args).map(|args| (args.count, args.interval));
+        assert_eq!(
+            parse(&["rq", "--region", "0,0,10,10", "--count", "5", "--interval", "2"]).unwrap(),
+            (Some(5), 2)
+        );
+        assert_eq!(parse(&["rq", "--workspace", "--count=3"]).unwrap(), (Some(3), 1));
+        assert_eq!(parse(&["rq"]).unwrap(), (None, 1));
+        // nobody to select again and again
+        assert!(parse(&["rq", "--count", "5"]).is_err());
+        assert!(parse(&["rq", "--workspace", "--count", "0"]).is_err());
+        assert!(parse(&["rq", "--workspace", "--interval", "2"]).is_err());
+    }
+
+    #[test]
+    fn test_format() {
+        let formats = |args: &[&str]| Args::try_parse_from(args).unwrap().format.formats();
+        assert_eq!(formats(&["rq"]), [Format::Qr]);
+        assert_eq!(formats(&["rq", "--format", "ean"]), [Format::Ean13]);
+        assert_eq!(formats(&["rq", "--format", "datamatrix"]), [Format::DataMatrix]);
+        assert_eq!(formats(&["rq", "--format", "auto"]), Format::ALL);
+    }
+
+    #[test]
+    fn test_invert() {
+        let parse = |args: &[&str]| Args::try_parse_from(args).map(|args| args.invert);
+        assert_eq!(parse(&["rq"]).unwrap(), Invert::Auto);
+        assert_eq!(parse(&["rq", "--invert", "always"]).unwrap(), Invert::Always);
+        assert!(parse(&["rq", "--invert", "yes"]).is_err());
+    }
+
+    #[test]
+    fn test_serve() {
+        let parse = |args: &[&str]| Args::try_parse_from(args).map(|args| (args.serve, args.client));
+        assert_eq!(parse(&["rq", "--serve", "--two-click"]).unwrap(), (true, false));
+        assert_eq!(parse(&["rq", "--client", "--delay", "1"]).unwrap(), (false, true));
+        assert!(parse(&["rq", "--serve", "--client"]).is_err());
+        // the results go to the client, not the server
+        assert!(parse(&["rq", "--serve", "--copy"]).is_err());
+        assert!(parse(&["rq", "--client", "--json"]).is_ok());
+    }
+
+    #[test]
+    fn test_kwin_interactive() {
+        let parse = |args: &[&str]| Args::try_parse_from(args).map(|args| args.kwin_interactive);
+        assert_eq!(parse(&["rq"]).unwrap(), None);
+        assert_eq!(
+            parse(&["rq", "--kwin-interactive"]).unwrap(),
+            Some(Interactive::Screen)
+        );
+        assert_eq!(
+            parse(&["rq", "--kwin-interactive", "window"]).unwrap(),
+            Some(Interactive::Window)
+        );
+        assert!(parse(&["rq", "--kwin-interactive", "--two-click"]).is_err());
+        // only meaningful for an interactive capture
+        assert!(parse(&["rq", "--decoration"]).is_err());
+        assert!(parse(&["rq", "--kwin-interactive", "window", "--decoration"]).is_ok());
+        assert!(parse(&["rq", "--window", "--decoration"]).is_ok());
+        assert!(parse(&["rq", "--window", "--kwin-interactive"]).is_err());
+    }
+
+    #[test]
+    fn test_parse_aspect() {
+        assert_eq!(
+            parse_aspect("1:1"),
+            Ok(Aspect {
+                width: 1,
+                height: 1
+            })
+        );
+        assert_eq!(
+            parse_aspect("16:9"),
+            Ok(Aspect {
+                width: 16,
+                height: 9
+            })
+        );
+        assert!(parse_aspect("16x9").is_err());
+        assert!(parse_aspect("0:1").is_err());
+        assert!(parse_aspect("a:1").is_err());
+    }
+
+    #[test]
+    fn test_parse_color() {
+        assert_eq!(
+            parse_color("64646480"),
+            Ok(Color::from_rgba8(0x64, 0x64, 0x64, 0x80))
+        );
+        assert_eq!(
+            parse_color("#ff000040"),
+            Ok(Color::from_rgba8(0xff, 0, 0, 0x40))
+        );
+        assert!(parse_color("646464").is_err());
+        assert!(parse_color("6464648g").is_err());
+        assert!(parse_color("+6464648").is_err());
+    }
+}
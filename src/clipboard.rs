@@ -0,0 +1,122 @@
+use anyhow::{anyhow, Result};
+use std::{fs::File, io::Write};
+use wayland_client::{
+    delegate_noop, event_created_child,
+    globals::{registry_queue_init, GlobalListContents},
+    protocol::{wl_registry::WlRegistry, wl_seat::WlSeat},
+    Connection, Dispatch, Proxy, QueueHandle,
+};
+use wayland_protocols_wlr::data_control::v1::client::{
+    zwlr_data_control_device_v1::{self, ZwlrDataControlDeviceV1},
+    zwlr_data_control_manager_v1::ZwlrDataControlManagerV1,
+    zwlr_data_control_offer_v1::ZwlrDataControlOfferV1,
+    zwlr_data_control_source_v1::{self, ZwlrDataControlSourceV1},
+};
+
+use crate::logger::{debug, warn};
+
+const MIME_TYPES: &[&str] = &[
+    "text/plain;charset=utf-8",
+    "text/plain",
+    "UTF8_STRING",
+    "STRING",
+    "TEXT",
+];
+
+struct ClipboardState {
+    text: String,
+    cancelled: bool,
+}
+
+/// Put `text` on the clipboard.
+///
+/// Blocks until another client takes over the selection, since Wayland
+/// clipboard contents are served by the owning process.
+pub fn copy(text: &str) -> Result<()> {
+    let conn = Connection::connect_to_env()?;
+    let (globals, mut event_queue) = registry_queue_init::<ClipboardState>(&conn)?;
+    let qh = event_queue.handle();
+
+    let manager: ZwlrDataControlManagerV1 = globals
+        .bind(&qh, 1..=2, ())
+        .map_err(|_| anyhow!("compositor does not support wlr-data-control"))?;
+    let seat: WlSeat = globals.bind(&qh, 1..=1, ())?;
+
+    let source = manager.create_data_source(&qh, ());
+    for mime in MIME_TYPES {
+        source.offer(mime.to_string());
+    }
+    let device = manager.get_data_device(&seat, &qh, ());
+    device.set_selection(Some(&source));
+
+    let mut state = ClipboardState {
+        text: text.to_owned(),
+        cancelled: false,
+    };
+    while !state.cancelled {
+        event_queue.blocking_dispatch(&mut state)?;
+    }
+
+    device.destroy();
+    source.destroy();
+    Ok(())
+}
+
+impl Dispatch<WlRegistry, GlobalListContents> for ClipboardState {
+    fn event(
+        _state: &mut Self,
+        _proxy: &WlRegistry,
+        _event: <WlRegistry as Proxy>::Event,
+        _data: &GlobalListContents,
+        _conn: &Connection,
+        _qh: &QueueHandle<Self>,
+    ) {
+    }
+}
+
+impl Dispatch<ZwlrDataControlSourceV1, ()> for ClipboardState {
+    fn event(
+        state: &mut Self,
+        _source: &ZwlrDataControlSourceV1,
+        event: zwlr_data_control_source_v1::Event,
+        _data: &(),
+        _conn: &Connection,
+        _qh: &QueueHandle<Self>,
+    ) {
+        use zwlr_data_control_source_v1::Event::*;
+        match event {
+            Send { mime_type, fd } => {
+                debug!("clipboard requested as {mime_type}");
+                if let Err(err) = File::from(fd).write_all(state.text.as_bytes()) {
+                    warn!("failed to send clipboard contents: {err}");
+                }
+            }
+            Cancelled => {
+                debug!("clipboard taken over by another client");
+                state.cancelled = true;
+            }
+            _ => {}
+        }
+    }
+}
+
+impl Dispatch<ZwlrDataControlDeviceV1, ()> for ClipboardState {
+    fn event(
+        _state: &mut Self,
+        _device: &ZwlrDataControlDeviceV1,
+        _event: zwlr_data_control_device_v1::Event,
+        _data: &(),
+        _conn: &Connection,
+        _qh: &QueueHandle<Self>,
+    ) {
+        // offers of other clients' selections are of no interest
+    }
+
+    event_created_child!(ClipboardState, ZwlrDataControlDeviceV1, [
+        zwlr_data_control_device_v1::EVT_DATA_OFFER_OPCODE => (ZwlrDataControlOfferV1, ()),
+    ]);
+}
+
+delegate_noop!(ClipboardState: ignore WlSeat);
+delegate_noop!(ClipboardState: ZwlrDataControlManagerV1);
+delegate_noop!(ClipboardState: ignore ZwlrDataControlOfferV1);
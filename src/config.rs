@@ -0,0 +1,202 @@
+//! Defaults for the command line options, kept in
+//! `$XDG_CONFIG_HOME/rq/config.toml`.
+//!
+//! Keys are the long options, e.g. `dim-color = "00000080"`, `fps = 60` or
+//! `two-click = true`, and whatever is given on the command line wins.
+use anyhow::{bail, Context, Result};
+use clap::{error::ErrorKind, parser::ValueSource, CommandFactory, FromArgMatches};
+use std::{
+    env,
+    ffi::OsString,
+    fs, io,
+    path::{Path, PathBuf},
+};
+
+use crate::cli::Args;
+use crate::xdg;
+
+/// `$XDG_CONFIG_HOME/rq/config.toml`, or `~/.config/rq/config.toml` when it
+/// is unset.
+fn config_path() -> Option<PathBuf> {
+    Some(xdg::base_dir("XDG_CONFIG_HOME", ".config")?.join("rq/config.toml"))
+}
+
+/// Parse the command line over the defaults of the config file.
+///
+/// Invalid arguments exit like `Args::parse` does, a broken config file is
+/// an error.
+pub fn parse_args() -> Result<Args> {
+    let defaults = match config_path() {
+        Some(path) => load(&path)?,
+        None => Vec::new(),
+    };
+    match merge(&defaults, env::args_os().collect()) {
+        Ok(args) => Ok(args),
+        Err(err) => err.exit(),
+    }
+}
+
+/// The options set in the config file at `path`, as argument id and the
+/// argument itself. A missing file sets none.
+fn load(path: &Path) -> Result<Vec<(String, String)>> {
+    let text = match fs::read_to_string(path) {
+        Ok(text) => text,
+        Err(err) if err.kind() == io::ErrorKind::NotFound => return Ok(Vec::new()),
+        Err(err) => return Err(err).with_context(|| format!("failed to read {}", path.display())),
+    };
+    let defaults = to_args(&text).with_context(|| format!("invalid config {}", path.display()))?;
+    // every mistake in the file is reported, not only those the command line
+    // doesn't happen to override, but what an option requires may be given
+    // there
+    let argv = ["rq".to_owned()]
+        .into_iter()
+        .chain(defaults.iter().map(|(_, arg)| arg.clone()));
+    let checked = Args::command().try_get_matches_from(argv);
+    if let Some(err) = checked.err().filter(|err| err.kind() != ErrorKind::MissingRequiredArgument) {
+        // only the message, the usage and hints are about the command line
+        let rendered = err.render().to_string();
+        let message = rendered.lines().next().unwrap_or_default();
+        let message = message.strip_prefix("error: ").unwrap_or(message);
+        bail!("invalid config {}: {message}", path.display());
+    }
+    Ok(defaults)
+}
+
+fn to_args(text: &str) -> Result<Vec<(String, String)>> {
+    let table = text.parse::<toml::Table>()?;
+    let mut args = Vec::new();
+    for (key, value) in table {
+        let arg = match value {
+            toml::Value::Boolean(true) => format!("--{key}"),
+            // the default of every flag
+            toml::Value::Boolean(false) => continue,
+            toml::Value::String(s) => format!("--{key}={s}"),
+            toml::Value::Integer(i) => format!("--{key}={i}"),
+            toml::Value::Float(f) => format!("--{key}={f}"),
+            _ => bail!("`{key}` must be a string, number or boolean"),
+        };
+        args.push((key.replace('-', "_"), arg));
+    }
+    Ok(args)
+}
+
+/// Parse `cli` with the `defaults` of the options it leaves out, skipping
+/// those conflicting with one it gives.
+fn merge(defaults: &[(String, String)], cli: Vec<OsString>) -> Result<Args, clap::Error> {
+    let mut command = Args::command();
+    // only to tell what is given, the defaults may satisfy what it requires
+    // and the parse of both below reports the rest
+    let given = command.clone().ignore_errors(true).try_get_matches_from(&cli)?;
+    let on_cli = |id: &str| given.value_source(id) == Some(ValueSource::CommandLine);
+    let on_cli = command
+        .get_arguments()
+        .filter(|arg| on_cli(arg.get_id().as_str()))
+        .collect::<Vec<_>>();
+    // either side may be the one declaring the conflict
+    let conflict = |a: &clap::Arg, b: &clap::Arg| {
+        let declared = |a, b: &clap::Arg| {
+            command
+                .get_arg_conflicts_with(a)
+                .iter()
+                .any(|c| c.get_id() == b.get_id())
+        };
+        declared(a, b) || declared(b, a)
+    };
+    let kept = defaults
+        .iter()
+        .filter(|(id, _)| {
+            let Some(arg) = command
+                .get_arguments()
+                .find(|arg| arg.get_id() == id.as_str())
+            else {
+                // unknown, the parser below tells
+                return true;
+            };
+            on_cli
+                .iter()
+                .all(|given| given.get_id() != arg.get_id() && !conflict(arg, given))
+        })
+        .map(|(_, arg)| OsString::from(arg))
+        .collect::<Vec<_>>();
+
+    let mut cli = cli.into_iter();
+    let argv = cli.next().into_iter().chain(kept).chain(cli);
+    let matches = command.try_get_matches_from_mut(argv)?;
+    Args::from_arg_matches(&matches)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::cli::CodeFormat;
+
+    fn parse(config: &str, cli: &[&str]) -> Args {
+        let defaults = to_args(config).unwrap();
+        merge(&defaults, cli.iter().map(OsString::from).collect()).unwrap()
+    }
+
+    #[test]
+    fn test_to_args() {
+        let args = to_args("fps = 60\ntwo-click = true\nfreeze = false\ndim-color = \"00000080\"");
+        assert_eq!(
+            args.unwrap(),
+            [
+                ("dim_color".to_owned(), "--dim-color=00000080".to_owned()),
+                ("fps".to_owned(), "--fps=60".to_owned()),
+                ("two_click".to_owned(), "--two-click".to_owned()),
+            ]
+        );
+        assert!(to_args("fps = [60]").is_err());
+        assert!(to_args("fps = ").is_err());
+    }
+
+    #[test]
+    fn test_merge() {
+        let config = "fps = 30\nformat = \"auto\"\ntwo-click = true";
+        let args = parse(config, &["rq"]);
+        assert_eq!(args.fps, Some(30));
+        assert_eq!(args.format, CodeFormat::Auto);
+        assert!(args.two_click);
+
+        // the command line wins
+        let args = parse(config, &["rq", "--fps", "120", "--format=qr"]);
+        assert_eq!(args.fps, Some(120));
+        assert_eq!(args.format, CodeFormat::Qr);
+
+        // --monitor conflicts with --two-click, which is dropped
+        let args = parse(config, &["rq", "--monitor", "DP-1"]);
+        assert!(!args.two_click);
+        assert_eq!(args.fps, Some(30));
+
+        // what an option requires may come from either side
+        assert!(parse("freeze = true", &["rq", "--magnifier"]).magnifier);
+        let args = parse("output-dir = \"/tmp\"", &["rq", "--name-with-payload"]);
+        assert!(args.name_with_payload);
+        let defaults = to_args("name-with-payload = true").unwrap();
+        let cli = ["rq", "--output-dir", "/tmp"].map(OsString::from).to_vec();
+        assert!(merge(&defaults, cli).unwrap().name_with_payload);
+        // but has to come from one
+        let cli = ["rq", "--magnifier"].map(OsString::from).to_vec();
+        assert!(merge(&[], cli).is_err());
+    }
+
+    #[test]
+    fn test_load() {
+        let dir = env::temp_dir().join(format!("rq-config-test-{}", std::process::id()));
+        fs::create_dir_all(&dir).unwrap();
+        let path = dir.join("config.toml");
+        assert!(load(&path).unwrap().is_empty());
+
+        fs::write(&path, "fps = 0").unwrap();
+        let err = load(&path).unwrap_err().to_string();
+        assert!(err.contains("--fps"), "{err}");
+        fs::write(&path, "no-such-option = 1").unwrap();
+        assert!(load(&path).is_err());
+        fs::write(&path, "border-width = 3").unwrap();
+        assert_eq!(load(&path).unwrap().len(), 1);
+        // --output-dir may be on the command line
+        fs::write(&path, "name-with-payload = true").unwrap();
+        assert_eq!(load(&path).unwrap().len(), 1);
+        fs::remove_dir_all(&dir).unwrap();
+    }
+}
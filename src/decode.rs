@@ -0,0 +1,593 @@
+use clap::ValueEnum;
+use image::{
+    imageops::{self, FilterType},
+    GrayImage, RgbaImage,
+};
+use rayon::prelude::*;
+use std::{
+    panic::{self, AssertUnwindSafe},
+    sync::Once,
+};
+
+use crate::logger::{debug, warn};
+
+mod barcode;
+mod payload;
+pub use payload::Payload;
+
+/// images with a shorter edge than this are upscaled before decoding
+const UPSCALE_THRESHOLD: u32 = 200;
+const UPSCALE_MAX_FACTOR: u32 = 3;
+/// edge of the tiles large images are decoded in
+const TILE_SIZE: u32 = 1280;
+/// how much neighboring tiles share, the largest code sure to be found whole
+const TILE_OVERLAP: u32 = 400;
+
+/// Tunables for `decode_image`.
+#[derive(Debug, Clone)]
+pub struct DecodeOptions {
+    /// upscale small images so codes with tiny modules are still found
+    pub upscale: bool,
+    /// kinds of codes to look for
+    pub formats: Vec<Format>,
+    /// image pixels per logical pixel of the screen it was captured from,
+    /// below 1 the image is upscaled back to the size the code had on screen
+    pub scale: f64,
+    /// images larger than this along either edge, once upscaled, are also
+    /// decoded as tiles of this size in parallel, 0 decodes them whole only
+    pub tile_size: u32,
+    /// pixels shared by neighboring tiles, a code straddling tiles is found
+    /// in one when it fits in the overlap, in the whole image otherwise
+    pub tile_overlap: u32,
+    /// when to look for light codes on a dark background
+    pub invert: Invert,
+}
+impl Default for DecodeOptions {
+    fn default() -> Self {
+        Self {
+            upscale: true,
+            formats: vec![Format::Qr],
+            scale: 1.,
+            tile_size: TILE_SIZE,
+            tile_overlap: TILE_OVERLAP,
+            invert: Invert::Auto,
+        }
+    }
+}
+
+/// Whether to decode the image with its brightness inverted, for codes drawn
+/// light on dark.
+#[derive(ValueEnum, Debug, Default, Clone, Copy, PartialEq, Eq)]
+pub enum Invert {
+    /// only when nothing is found as it is
+    #[default]
+    Auto,
+    /// only light codes are looked for
+    Always,
+    /// only dark codes are looked for
+    Never,
+}
+
+/// Kinds of codes that can be decoded.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Format {
+    Qr,
+    Ean13,
+    Code128,
+    DataMatrix,
+}
+impl Format {
+    pub const ALL: [Self; 4] = [Self::Qr, Self::Ean13, Self::Code128, Self::DataMatrix];
+
+    /// The name used in the JSON output.
+    pub fn name(self) -> &'static str {
+        match self {
+            Self::Qr => "qr",
+            Self::Ean13 => "ean13",
+            Self::Code128 => "code128",
+            Self::DataMatrix => "datamatrix",
+        }
+    }
+
+    /// The format of `name` in the JSON output.
+    pub fn from_name(name: &str) -> Option<Self> {
+        Self::ALL.into_iter().find(|format| format.name() == name)
+    }
+}
+
+/// A code found in an image.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct DecodeResult {
+    pub text: String,
+    pub format: Format,
+    /// corners of the code in image pixels, clockwise starting at the top-left
+    /// of the code as it is meant to be read
+    pub bounds: [(i32, i32); 4],
+}
+impl DecodeResult {
+    /// The structured content of the code, if it has any.
+    pub fn payload(&self) -> Option<Payload> {
+        Payload::parse(&self.text)
+    }
+
+    /// The smallest upright rectangle around the code, as x, y, width and
+    /// height in image pixels.
+    pub fn bounding_box(&self) -> (i32, i32, u32, u32) {
+        let xs = self.bounds.map(|(x, _)| x);
+        let ys = self.bounds.map(|(_, y)| y);
+        let (left, right) = (xs.iter().min().unwrap(), xs.iter().max().unwrap());
+        let (top, bottom) = (ys.iter().min().unwrap(), ys.iter().max().unwrap());
+        (*left, *top, right.abs_diff(*left), bottom.abs_diff(*top))
+    }
+}
+
+/// Find and decode every code of `options.formats` in `image`.
+///
+/// Codes that are detected but fail to decode are skipped.
+pub fn decode_image(image: &RgbaImage, options: &DecodeOptions) -> Vec<DecodeResult> {
+    let (mut luma, factor) = preprocess(image, options);
+    let factor = factor as i32;
+    let unscale = |results: Vec<DecodeResult>| {
+        results
+            .into_iter()
+            .map(|r| DecodeResult {
+                bounds: r.bounds.map(|(x, y)| (x / factor, y / factor)),
+                ..r
+            })
+            .collect()
+    };
+
+    if options.invert != Invert::Always {
+        let results = decode_tiled(luma.clone(), options);
+        if !results.is_empty() || options.invert == Invert::Never {
+            return unscale(results);
+        }
+    }
+    imageops::invert(&mut luma);
+    let results = decode_tiled(luma, options);
+    if !results.is_empty() && options.invert == Invert::Auto {
+        debug!("decoded after inverting");
+    }
+    unscale(results)
+}
+
+/// Decode every one of `frames`, e.g. captures of an animation, into the
+/// codes found in any of them, each payload once.
+///
+/// A code seen in several frames is reported with its bounds in the first.
+pub fn decode_frames(frames: &[RgbaImage], options: &DecodeOptions) -> Vec<DecodeResult> {
+    let found = frames
+        .par_iter()
+        .map(|frame| decode_image(frame, options))
+        .collect::<Vec<_>>();
+    let mut results: Vec<DecodeResult> = Vec::new();
+    for result in found.into_iter().flatten() {
+        let seen = results
+            .iter()
+            .any(|r| r.format == result.format && r.text == result.text);
+        if !seen {
+            results.push(result);
+        }
+    }
+    results
+}
+
+/// Decode the `width`x`height` RGBA pixels in `buf` with the default options,
+/// looking for every format.
+///
+/// The entry point for benchmarks and fuzzing, a buffer of the wrong size
+/// decodes to nothing.
+#[cfg_attr(not(test), allow(dead_code))]
+pub fn decode_rgba(width: u32, height: u32, buf: Vec<u8>) -> Vec<String> {
+    let Some(image) = RgbaImage::from_vec(width, height, buf) else {
+        warn!("buffer does not hold a {width}x{height} image");
+        return Vec::new();
+    };
+    let options = DecodeOptions {
+        formats: Format::ALL.to_vec(),
+        ..Default::default()
+    };
+    decode_image(&image, &options)
+        .into_iter()
+        .map(|result| result.text)
+        .collect()
+}
+
+/// Convert to grayscale, and upscale by an integer factor when the image is
+/// small or was captured below the screen's resolution.
+fn preprocess(image: &RgbaImage, options: &DecodeOptions) -> (GrayImage, u32) {
+    let luma = image::imageops::grayscale(image);
+    let short_edge = luma.width().min(luma.height());
+    if !options.upscale || short_edge == 0 {
+        return (luma, 1);
+    }
+
+    let for_size = if short_edge < UPSCALE_THRESHOLD {
+        UPSCALE_THRESHOLD.div_ceil(short_edge).max(2)
+    } else {
+        1
+    };
+    let for_scale = if options.scale > 0. && options.scale < 1. {
+        (1. / options.scale).ceil() as u32
+    } else {
+        1
+    };
+    let factor = for_size.max(for_scale).min(UPSCALE_MAX_FACTOR);
+    if factor == 1 {
+        return (luma, 1);
+    }
+    debug!("upscaling {}x{} image by {factor}", luma.width(), luma.height());
+    let luma = image::imageops::resize(
+        &luma,
+        luma.width() * factor,
+        luma.height() * factor,
+        FilterType::Nearest,
+    );
+    (luma, factor)
+}
+
+/// Decode `luma` whole, and as overlapping tiles in parallel when it is
+/// larger than `options.tile_size`.
+///
+/// The tiles find small codes the whole image is too busy for, the whole
+/// image the codes too large for any tile.
+fn decode_tiled(luma: GrayImage, options: &DecodeOptions) -> Vec<DecodeResult> {
+    let (width, height) = luma.dimensions();
+    let size = options.tile_size;
+    if size == 0 || (width <= size && height <= size) {
+        return decode_luma(luma, &options.formats);
+    }
+
+    let overlap = options.tile_overlap.min(size / 2);
+    let xs = tile_starts(width, size, overlap);
+    let ys = tile_starts(height, size, overlap);
+    debug!("decoding {width}x{height} image whole and as {} tiles", xs.len() * ys.len());
+    let (whole, tiled) = rayon::join(
+        || decode_luma(luma.clone(), &options.formats),
+        || {
+            ys.iter()
+                .flat_map(|&y| xs.iter().map(move |&x| (x, y)))
+                .collect::<Vec<_>>()
+                .into_par_iter()
+                .flat_map_iter(|(x, y)| {
+                    let tile = image::imageops::crop_imm(&luma, x, y, size, size).to_image();
+                    decode_luma(tile, &options.formats)
+                        .into_iter()
+                        .map(move |r| DecodeResult {
+                            bounds: r.bounds.map(|(bx, by)| (bx + x as i32, by + y as i32)),
+                            ..r
+                        })
+                })
+                .collect::<Vec<_>>()
+        },
+    );
+
+    // a code is found whole and by every tile holding it
+    let mut results: Vec<DecodeResult> = Vec::new();
+    for result in whole.into_iter().chain(tiled) {
+        let seen = results.iter().any(|r| {
+            r.format == result.format && r.text == result.text && boxes_intersect(r, &result)
+        });
+        if !seen {
+            results.push(result);
+        }
+    }
+    results
+}
+
+/// Where tiles of `size` start along an edge of `len`, `overlap` apart from
+/// each other and the last one ending at the edge.
+fn tile_starts(len: u32, size: u32, overlap: u32) -> Vec<u32> {
+    if len <= size {
+        return vec![0];
+    }
+    let step = size - overlap;
+    let last = len - size;
+    let mut starts = (0..last).step_by(step as usize).collect::<Vec<_>>();
+    starts.push(last);
+    starts
+}
+
+fn boxes_intersect(a: &DecodeResult, b: &DecodeResult) -> bool {
+    let (ax, ay, aw, ah) = a.bounding_box();
+    let (bx, by, bw, bh) = b.bounding_box();
+    ax <= bx + bw as i32 && bx <= ax + aw as i32 && ay <= by + bh as i32 && by <= ay + ah as i32
+}
+
+/// Decode the codes of `formats` in `luma`, bounds are in its own pixels.
+fn decode_luma(luma: GrayImage, formats: &[Format]) -> Vec<DecodeResult> {
+    let mut results = barcode::decode(&luma, formats);
+    if !formats.contains(&Format::Qr) {
+        return results;
+    }
+
+    quiet_rqrr_panics();
+    let mut img = rqrr::PreparedImage::prepare(luma);
+    let detected = panic::catch_unwind(AssertUnwindSafe(|| {
+        img.detect_grids()
+            .into_iter()
+            .filter_map(|grid| {
+                let bounds = grid.bounds.map(|p| (p.x, p.y));
+                match panic::catch_unwind(AssertUnwindSafe(|| grid.decode())) {
+                    Ok(Ok((_meta, text))) => Some(DecodeResult {
+                        text,
+                        format: Format::Qr,
+                        bounds,
+                    }),
+                    Ok(Err(err)) => {
+                        warn!("failed to decode code at {bounds:?}: {err}");
+                        None
+                    }
+                    Err(_) => {
+                        warn!("failed to decode code at {bounds:?}");
+                        None
+                    }
+                }
+            })
+            .collect::<Vec<_>>()
+    }));
+    match detected {
+        Ok(detected) => results.extend(detected),
+        Err(_) => warn!("failed to detect codes, their modules may be too small"),
+    }
+    results
+}
+
+/// rqrr asserts on some codes it can't make sense of, e.g. with modules
+/// under a pixel wide. Those panics are caught, keep them from printing too.
+fn quiet_rqrr_panics() {
+    static HOOK: Once = Once::new();
+    HOOK.call_once(|| {
+        let previous = panic::take_hook();
+        panic::set_hook(Box::new(move |info| {
+            if !info.location().is_some_and(|at| at.file().contains("rqrr")) {
+                previous(info);
+            }
+        }));
+    });
+}
+
+#[cfg(test)]
+pub(crate) mod tests {
+    use super::*;
+    use image::Rgba;
+    use qrcode::{Color, QrCode};
+
+    /// Render `text` as a QR code with a 4 module quiet zone.
+    pub fn qr_image(text: &str, module_size: u32) -> RgbaImage {
+        let code = QrCode::new(text).unwrap();
+        let width = code.width() as u32;
+        let colors = code.to_colors();
+        let size = (width + 8) * module_size;
+        RgbaImage::from_fn(size, size, |x, y| {
+            let (mx, my) = (x / module_size, y / module_size);
+            let dark = (4..width + 4).contains(&mx)
+                && (4..width + 4).contains(&my)
+                && colors[((my - 4) * width + mx - 4) as usize] == Color::Dark;
+            if dark {
+                Rgba([0, 0, 0, 0xff])
+            } else {
+                Rgba([0xff, 0xff, 0xff, 0xff])
+            }
+        })
+    }
+
+    fn texts(results: &[DecodeResult]) -> Vec<&str> {
+        let mut texts = results.iter().map(|r| r.text.as_str()).collect::<Vec<_>>();
+        texts.sort();
+        texts
+    }
+
+    #[test]
+    fn test_decode_single() {
+        let image = qr_image("hello", 4);
+        let results = decode_image(&image, &DecodeOptions::default());
+        assert_eq!(texts(&results), ["hello"]);
+        // the quiet zone is 4 modules wide
+        assert_eq!(results[0].bounds[0], (16, 16));
+    }
+
+    #[test]
+    fn test_bounding_box() {
+        let result = DecodeResult {
+            text: String::new(),
+            format: Format::Qr,
+            // tilted, starting at the top
+            bounds: [(50, 10), (90, 50), (50, 90), (10, 50)],
+        };
+        assert_eq!(result.bounding_box(), (10, 10, 80, 80));
+
+        let image = qr_image("box", 4);
+        let results = decode_image(&image, &DecodeOptions::default());
+        let (x, y, w, h) = results[0].bounding_box();
+        assert_eq!((x, y), (16, 16));
+        // at least the 21 modules of the code, and within the image
+        assert!(w >= 84 && h >= 84, "{w}x{h}");
+        assert!(x as u32 + w <= image.width() && y as u32 + h <= image.height());
+    }
+
+    #[test]
+    fn test_decode_multiple() {
+        let first = qr_image("https://example.com/first", 4);
+        let second = qr_image("second code", 4);
+        let mut image = RgbaImage::from_pixel(
+            first.width() + second.width(),
+            first.height().max(second.height()),
+            Rgba([0xff, 0xff, 0xff, 0xff]),
+        );
+        image::imageops::replace(&mut image, &first, 0, 0);
+        image::imageops::replace(&mut image, &second, first.width() as i64, 0);
+
+        let results = decode_image(&image, &DecodeOptions::default());
+        assert_eq!(texts(&results), ["https://example.com/first", "second code"]);
+        assert!(results.iter().all(|r| r.bounds[0] != r.bounds[2]));
+    }
+
+    #[test]
+    fn test_tile_starts() {
+        assert_eq!(tile_starts(500, 1000, 100), [0]);
+        assert_eq!(tile_starts(1000, 1000, 100), [0]);
+        assert_eq!(tile_starts(2500, 1000, 200), [0, 800, 1500]);
+        assert_eq!(tile_starts(1700, 1000, 300), [0, 700]);
+    }
+
+    #[test]
+    fn test_decode_tiled() {
+        // one code straddling the first two tiles, one only in the last
+        let straddling = qr_image("straddling", 4);
+        let corner = qr_image("corner", 4);
+        let mut image = RgbaImage::from_pixel(700, 300, Rgba([0xff, 0xff, 0xff, 0xff]));
+        image::imageops::replace(&mut image, &straddling, 200, 50);
+        image::imageops::replace(&mut image, &corner, 560, 160);
+        let options = DecodeOptions {
+            tile_size: 300,
+            tile_overlap: 150,
+            ..Default::default()
+        };
+        let results = decode_image(&image, &options);
+        assert_eq!(texts(&results), ["corner", "straddling"]);
+        let straddling = results.iter().find(|r| r.text == "straddling").unwrap();
+        // back in the coordinates of the whole image, quiet zone included
+        assert_eq!(straddling.bounds[0], (216, 66));
+        // the same as decoding it whole
+        let whole = DecodeOptions {
+            tile_size: 0,
+            ..Default::default()
+        };
+        assert_eq!(texts(&decode_image(&image, &whole)), ["corner", "straddling"]);
+    }
+
+    #[test]
+    fn test_decode_tiled_straddling_overlap() {
+        // wider than the overlap, across the boundary of the tiles at 0 and
+        // 150, so in neither
+        let wide = qr_image("wide", 4);
+        assert!((50..200).contains(&wide.width()));
+        let mut image = RgbaImage::from_pixel(700, 300, Rgba([0xff, 0xff, 0xff, 0xff]));
+        image::imageops::replace(&mut image, &wide, 118, 20);
+        let options = DecodeOptions {
+            tile_size: 200,
+            tile_overlap: 50,
+            ..Default::default()
+        };
+        assert_eq!(tile_starts(700, 200, 50), [0, 150, 300, 450, 500]);
+        let results = decode_image(&image, &options);
+        assert_eq!(texts(&results), ["wide"]);
+        assert_eq!(results[0].bounds[0], (134, 36));
+    }
+
+    #[test]
+    fn test_decode_frames() {
+        // the code partly covered in the second frame
+        let first = qr_image("first", 4);
+        let mut covered = first.clone();
+        image::imageops::replace(
+            &mut covered,
+            &RgbaImage::from_pixel(60, 60, Rgba([0x80, 0x80, 0x80, 0xff])),
+            20,
+            20,
+        );
+        let frames = [first.clone(), covered, qr_image("second", 4), first];
+        let results = decode_frames(&frames, &DecodeOptions::default());
+        assert_eq!(texts(&results), ["first", "second"]);
+        assert!(decode_frames(&[], &DecodeOptions::default()).is_empty());
+    }
+
+    #[test]
+    fn test_preprocess_scale() {
+        let image = RgbaImage::new(300, 400);
+        let factor = |scale| {
+            let options = DecodeOptions {
+                scale,
+                ..Default::default()
+            };
+            preprocess(&image, &options).1
+        };
+        assert_eq!(factor(1.), 1);
+        // HiDPI captures already have more pixels than the screen shows
+        assert_eq!(factor(2.), 1);
+        assert_eq!(factor(0.5), 2);
+        assert_eq!(factor(0.1), UPSCALE_MAX_FACTOR);
+        assert_eq!(preprocess(&image, &DecodeOptions::default()).0.width(), 300);
+    }
+
+    #[test]
+    fn test_decode_tiny_needs_upscale() {
+        // one pixel per module
+        let image = qr_image("tiny", 1);
+        assert!(image.width() < UPSCALE_THRESHOLD);
+
+        let results = decode_image(&image, &DecodeOptions::default());
+        assert_eq!(texts(&results), ["tiny"]);
+        assert_eq!(results[0].bounds[0], (4, 4));
+
+        // rqrr gives up on modules this small
+        let options = DecodeOptions {
+            upscale: false,
+            ..Default::default()
+        };
+        assert!(decode_image(&image, &options).is_empty());
+    }
+
+    #[test]
+    fn test_decode_inverted() {
+        let mut image = qr_image("light on dark", 4);
+        image::imageops::invert(&mut image);
+        let decode = |image: &RgbaImage, invert| {
+            let options = DecodeOptions {
+                invert,
+                ..Default::default()
+            };
+            texts(&decode_image(image, &options)).join(",")
+        };
+        assert_eq!(decode(&image, Invert::Auto), "light on dark");
+        assert_eq!(decode(&image, Invert::Always), "light on dark");
+        assert_eq!(decode(&image, Invert::Never), "");
+
+        // always inverting loses the usual dark on light codes
+        let image = qr_image("dark on light", 4);
+        assert_eq!(decode(&image, Invert::Auto), "dark on light");
+        assert_eq!(decode(&image, Invert::Always), "");
+    }
+
+    #[test]
+    fn test_decode_nothing() {
+        let image = RgbaImage::from_pixel(64, 64, Rgba([0xff, 0xff, 0xff, 0xff]));
+        assert!(decode_image(&image, &DecodeOptions::default()).is_empty());
+    }
+
+    #[test]
+    fn test_decode_rgba() {
+        let image = qr_image("rgba", 4);
+        let (width, height) = image.dimensions();
+        assert_eq!(decode_rgba(width, height, image.into_raw()), ["rgba"]);
+        assert!(decode_rgba(width, height, vec![0; 16]).is_empty());
+    }
+
+    /// Every `NAME.png` in tests/fixtures decodes to the payloads listed in
+    /// `NAME.txt`, one per line in any order.
+    #[test]
+    fn test_fixtures() {
+        let dir = std::path::Path::new(env!("CARGO_MANIFEST_DIR")).join("tests/fixtures");
+        let mut checked = 0;
+        for entry in std::fs::read_dir(&dir).unwrap() {
+            let path = entry.unwrap().path();
+            if path.extension().is_none_or(|ext| ext != "png") {
+                continue;
+            }
+            let expected = std::fs::read_to_string(path.with_extension("txt")).unwrap();
+            let mut expected = expected.lines().collect::<Vec<_>>();
+            expected.sort();
+
+            let image = image::open(&path).unwrap().to_rgba8();
+            let (width, height) = image.dimensions();
+            let mut decoded = decode_rgba(width, height, image.into_raw());
+            decoded.sort();
+
+            assert_eq!(decoded, expected, "{}", path.display());
+            checked += 1;
+        }
+        assert!(checked > 0, "no fixtures in {}", dir.display());
+    }
+}
+
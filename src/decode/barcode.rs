@@ -0,0 +1,93 @@
+//! Barcodes other than QR codes, EAN-13, Code 128 and DataMatrix, read with
+//! rxing.
+use image::GrayImage;
+use rxing::{helpers, BarcodeFormat, DecodeHints, Exceptions, RXingResult};
+use std::collections::HashSet;
+
+use super::{DecodeResult, Format};
+use crate::logger::warn;
+
+impl Format {
+    fn barcode_format(self) -> Option<BarcodeFormat> {
+        match self {
+            // rqrr reads those
+            Self::Qr => None,
+            Self::Ean13 => Some(BarcodeFormat::EAN_13),
+            Self::Code128 => Some(BarcodeFormat::CODE_128),
+            Self::DataMatrix => Some(BarcodeFormat::DATA_MATRIX),
+        }
+    }
+}
+
+/// Decode the barcodes of `formats` in `luma`, bounds are in its own pixels.
+pub fn decode(luma: &GrayImage, formats: &[Format]) -> Vec<DecodeResult> {
+    let wanted = formats
+        .iter()
+        .filter_map(|format| format.barcode_format())
+        .collect::<HashSet<_>>();
+    let (width, height) = luma.dimensions();
+    if wanted.is_empty() || width == 0 || height == 0 {
+        return Vec::new();
+    }
+
+    let mut hints = DecodeHints {
+        PossibleFormats: Some(wanted),
+        ..Default::default()
+    };
+    match helpers::detect_multiple_in_luma_with_hints(luma.to_vec(), width, height, &mut hints) {
+        Ok(found) => found.iter().filter_map(to_result).collect(),
+        Err(Exceptions::NotFoundException(_)) => Vec::new(),
+        Err(err) => {
+            warn!("failed to decode barcodes: {err}");
+            Vec::new()
+        }
+    }
+}
+
+/// rxing reports the ends of the scanned row for linear barcodes and the
+/// corners of 2D ones, in no fixed order, so the bounds are the upright box
+/// around them.
+fn to_result(found: &RXingResult) -> Option<DecodeResult> {
+    let format = match found.getBarcodeFormat() {
+        BarcodeFormat::EAN_13 => Format::Ean13,
+        BarcodeFormat::CODE_128 => Format::Code128,
+        BarcodeFormat::DATA_MATRIX => Format::DataMatrix,
+        _ => return None,
+    };
+    let points = found.getPoints();
+    let xs = points.iter().map(|p| p.x.round() as i32);
+    let ys = points.iter().map(|p| p.y.round() as i32);
+    let (left, right) = (xs.clone().min()?, xs.max()?);
+    let (top, bottom) = (ys.clone().min()?, ys.max()?);
+    Some(DecodeResult {
+        text: found.getText().to_owned(),
+        format,
+        bounds: [(left, top), (right, top), (right, bottom), (left, bottom)],
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn fixture(name: &str) -> GrayImage {
+        let dir = std::path::Path::new(env!("CARGO_MANIFEST_DIR")).join("tests/fixtures");
+        image::open(dir.join(name)).unwrap().to_luma8()
+    }
+
+    #[test]
+    fn test_decode_datamatrix() {
+        let luma = fixture("datamatrix.png");
+        let results = decode(&luma, &[Format::DataMatrix]);
+        assert_eq!(results.len(), 1);
+        assert_eq!(results[0].format, Format::DataMatrix);
+        assert_eq!(results[0].text, "SN 4711-0815/A");
+        let (x, y, w, h) = results[0].bounding_box();
+        assert!(x >= 0 && y >= 0 && w > 0 && h > 0, "{:?}", results[0].bounds);
+        assert!(x as u32 + w <= luma.width() && y as u32 + h <= luma.height());
+
+        // only the formats asked for
+        assert!(decode(&luma, &[Format::Ean13, Format::Code128]).is_empty());
+        assert!(decode(&luma, &[Format::Qr]).is_empty());
+    }
+}
@@ -0,0 +1,384 @@
+//! Structured payloads some QR codes carry, e.g. WiFi credentials or contacts.
+use serde::Serialize;
+use std::fmt;
+
+/// A payload in a scheme rq knows about.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize)]
+#[serde(rename_all = "lowercase")]
+pub enum Payload {
+    Wifi(WifiCredentials),
+    Contact(Contact),
+}
+
+impl Payload {
+    /// Recognize `text`, `None` for anything that is just text.
+    pub fn parse(text: &str) -> Option<Self> {
+        WifiCredentials::parse(text)
+            .map(Self::Wifi)
+            .or_else(|| Contact::parse(text).map(Self::Contact))
+    }
+}
+
+impl fmt::Display for Payload {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::Wifi(wifi) => wifi.fmt(f),
+            Self::Contact(contact) => contact.fmt(f),
+        }
+    }
+}
+
+/// A network to join, from a `WIFI:T:WPA;S:ssid;P:password;;` code.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize)]
+pub struct WifiCredentials {
+    pub ssid: String,
+    pub password: Option<String>,
+    /// e.g. WPA or WEP, `None` for an open network
+    pub security: Option<String>,
+    pub hidden: bool,
+}
+
+impl WifiCredentials {
+    fn parse(text: &str) -> Option<Self> {
+        let fields = strip_prefix_ignore_case(text, "WIFI:")?;
+        let mut wifi = Self {
+            ssid: String::new(),
+            password: None,
+            security: None,
+            hidden: false,
+        };
+        let mut has_ssid = false;
+        for field in split_unescaped(fields, ';') {
+            let Some((key, value)) = field.split_once(':') else {
+                continue;
+            };
+            let value = unescape(value);
+            match key {
+                "S" => {
+                    wifi.ssid = value;
+                    has_ssid = true;
+                }
+                "P" if !value.is_empty() => wifi.password = Some(value),
+                "T" if !value.is_empty() && !value.eq_ignore_ascii_case("nopass") => {
+                    wifi.security = Some(value)
+                }
+                "H" => wifi.hidden = value.eq_ignore_ascii_case("true"),
+                _ => {}
+            }
+        }
+        has_ssid.then_some(wifi)
+    }
+}
+
+impl fmt::Display for WifiCredentials {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "WiFi network: {}", self.ssid)?;
+        if self.hidden {
+            write!(f, " (hidden)")?;
+        }
+        write!(
+            f,
+            "\nSecurity: {}",
+            self.security.as_deref().unwrap_or("none")
+        )?;
+        if let Some(password) = &self.password {
+            write!(f, "\nPassword: {password}")?;
+        }
+        Ok(())
+    }
+}
+
+/// A person or business, from a vCard or a `MECARD:N:Doe,John;TEL:123;;` code.
+#[derive(Debug, Clone, Default, PartialEq, Eq, Serialize)]
+pub struct Contact {
+    pub name: Option<String>,
+    pub organization: Option<String>,
+    pub phones: Vec<String>,
+    pub emails: Vec<String>,
+    pub urls: Vec<String>,
+}
+
+impl Contact {
+    fn parse(text: &str) -> Option<Self> {
+        if let Some(fields) = strip_prefix_ignore_case(text, "MECARD:") {
+            return Some(Self::parse_mecard(fields));
+        }
+        let text = text.trim_start();
+        strip_prefix_ignore_case(text, "BEGIN:VCARD").map(|_| Self::parse_vcard(text))
+    }
+
+    fn parse_mecard(fields: &str) -> Self {
+        let mut contact = Self::default();
+        for field in split_unescaped(fields, ';') {
+            let Some((key, value)) = field.split_once(':') else {
+                continue;
+            };
+            match key.to_ascii_uppercase().as_str() {
+                // last name first, e.g. N:Doe,John
+                "N" => {
+                    let parts = split_unescaped(value, ',');
+                    let name = parts
+                        .iter()
+                        .rev()
+                        .map(|p| unescape(p))
+                        .filter(|p| !p.is_empty())
+                        .collect::<Vec<_>>();
+                    contact.name = Some(name.join(" ")).filter(|n| !n.is_empty());
+                }
+                "ORG" => contact.organization = Some(unescape(value)),
+                "TEL" => contact.phones.push(unescape(value)),
+                "EMAIL" => contact.emails.push(unescape(value)),
+                "URL" => contact.urls.push(unescape(value)),
+                _ => {}
+            }
+        }
+        contact
+    }
+
+    fn parse_vcard(text: &str) -> Self {
+        let mut contact = Self::default();
+        let mut structured_name = None;
+        for line in unfold(text) {
+            let Some((name, value)) = line.split_once(':') else {
+                continue;
+            };
+            // drop the group in item1.TEL and the parameters in TEL;TYPE=CELL
+            let name = name.split(';').next().unwrap_or_default();
+            let name = name.rsplit('.').next().unwrap_or_default();
+            match name.to_ascii_uppercase().as_str() {
+                "FN" => contact.name = Some(unescape_vcard(value)),
+                // family;given;additional;prefixes;suffixes
+                "N" => {
+                    let parts = split_unescaped(value, ';');
+                    let name = [parts.get(1), parts.first()]
+                        .into_iter()
+                        .flatten()
+                        .map(|p| unescape_vcard(p))
+                        .filter(|p| !p.is_empty())
+                        .collect::<Vec<_>>();
+                    structured_name = Some(name.join(" "));
+                }
+                "ORG" => {
+                    let units = split_unescaped(value, ';');
+                    let units = units.iter().map(|u| unescape_vcard(u)).collect::<Vec<_>>();
+                    contact.organization = Some(units.join(", "));
+                }
+                "TEL" => contact.phones.push(unescape_vcard(value)),
+                "EMAIL" => contact.emails.push(unescape_vcard(value)),
+                "URL" => contact.urls.push(unescape_vcard(value)),
+                _ => {}
+            }
+        }
+        contact.name = contact.name.or(structured_name).filter(|n| !n.is_empty());
+        contact
+    }
+}
+
+impl fmt::Display for Contact {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(
+            f,
+            "Contact: {}",
+            self.name.as_deref().unwrap_or("(no name)")
+        )?;
+        if let Some(organization) = &self.organization {
+            write!(f, "\nOrganization: {organization}")?;
+        }
+        for phone in &self.phones {
+            write!(f, "\nPhone: {phone}")?;
+        }
+        for email in &self.emails {
+            write!(f, "\nEmail: {email}")?;
+        }
+        for url in &self.urls {
+            write!(f, "\nURL: {url}")?;
+        }
+        Ok(())
+    }
+}
+
+fn strip_prefix_ignore_case<'a>(text: &'a str, prefix: &str) -> Option<&'a str> {
+    let head = text.get(..prefix.len())?;
+    head.eq_ignore_ascii_case(prefix)
+        .then(|| &text[prefix.len()..])
+}
+
+/// Split `text` at every `sep` not escaped with a backslash, escapes are kept.
+fn split_unescaped(text: &str, sep: char) -> Vec<&str> {
+    let mut parts = Vec::new();
+    let mut start = 0;
+    let mut escaped = false;
+    for (i, c) in text.char_indices() {
+        match c {
+            _ if escaped => escaped = false,
+            '\\' => escaped = true,
+            c if c == sep => {
+                parts.push(&text[start..i]);
+                start = i + c.len_utf8();
+            }
+            _ => {}
+        }
+    }
+    parts.push(&text[start..]);
+    parts
+}
+
+/// Drop the backslashes escaping `\;`, `\,`, `\:`, `\"` and `\\`.
+fn unescape(text: &str) -> String {
+    let mut out = String::with_capacity(text.len());
+    let mut chars = text.chars();
+    while let Some(c) = chars.next() {
+        match c {
+            '\\' => out.extend(chars.next()),
+            c => out.push(c),
+        }
+    }
+    out
+}
+
+/// Like `unescape`, and `\n` is a line break.
+fn unescape_vcard(text: &str) -> String {
+    let mut out = String::with_capacity(text.len());
+    let mut chars = text.chars();
+    while let Some(c) = chars.next() {
+        match c {
+            '\\' => match chars.next() {
+                Some('n' | 'N') => out.push('\n'),
+                next => out.extend(next),
+            },
+            c => out.push(c),
+        }
+    }
+    out
+}
+
+/// The lines of a vCard, with folded lines joined back together.
+fn unfold(text: &str) -> Vec<String> {
+    let mut lines: Vec<String> = Vec::new();
+    for line in text.lines() {
+        match (line.strip_prefix([' ', '\t']), lines.last_mut()) {
+            (Some(continued), Some(last)) => last.push_str(continued),
+            _ => lines.push(line.to_owned()),
+        }
+    }
+    lines
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn wifi(text: &str) -> Option<WifiCredentials> {
+        match Payload::parse(text)? {
+            Payload::Wifi(wifi) => Some(wifi),
+            _ => None,
+        }
+    }
+
+    fn contact(text: &str) -> Option<Contact> {
+        match Payload::parse(text)? {
+            Payload::Contact(contact) => Some(contact),
+            _ => None,
+        }
+    }
+
+    const VCARD: &str = "BEGIN:VCARD\r
+VERSION:3.0\r
+N:Doe;John;;Dr.;\r
+FN:John Doe\r
+ORG:Example\\, Inc.;Research\r
+TEL;TYPE=CELL:+1 555 0100\r
+item1.TEL;TYPE=WORK,VOICE:+1 555 0199\r
+EMAIL;TYPE=INTERNET:john@exa\r
+ mple.com\r
+NOTE:first\\nsecond\r
+END:VCARD\r
+";
+
+    const MECARD: &str = r"MECARD:N:Doe,John;TEL:+15550100;TEL:+15550199;EMAIL:john@example.com;URL:https\://example.com;NOTE:hi\;there;;";
+
+    #[test]
+    fn test_wifi() {
+        assert_eq!(
+            wifi("WIFI:T:WPA;S:home;P:secret;;"),
+            Some(WifiCredentials {
+                ssid: "home".into(),
+                password: Some("secret".into()),
+                security: Some("WPA".into()),
+                hidden: false,
+            })
+        );
+        // any order, open and hidden
+        assert_eq!(
+            wifi("wifi:H:true;S:cafe;T:nopass;P:;;"),
+            Some(WifiCredentials {
+                ssid: "cafe".into(),
+                password: None,
+                security: None,
+                hidden: true,
+            })
+        );
+        assert_eq!(wifi("WIFI:T:WPA;P:secret;;"), None);
+        assert_eq!(wifi("https://example.com"), None);
+        assert_eq!(Payload::parse("WIFI"), None);
+    }
+
+    #[test]
+    fn test_wifi_escaped() {
+        let wifi = wifi(r#"WIFI:S:my\;net\:work;T:WPA;P:p\\a\,s\"s\;;;"#).unwrap();
+        assert_eq!(wifi.ssid, "my;net:work");
+        assert_eq!(wifi.password.as_deref(), Some(r#"p\a,s"s;"#));
+    }
+
+    #[test]
+    fn test_vcard() {
+        assert_eq!(
+            contact(VCARD),
+            Some(Contact {
+                name: Some("John Doe".into()),
+                organization: Some("Example, Inc., Research".into()),
+                phones: vec!["+1 555 0100".into(), "+1 555 0199".into()],
+                emails: vec!["john@example.com".into()],
+                urls: vec![],
+            })
+        );
+        // only the structured name
+        let contact = contact("BEGIN:VCARD\nVERSION:4.0\nN:Doe;Jane;;;\nEND:VCARD").unwrap();
+        assert_eq!(contact.name.as_deref(), Some("Jane Doe"));
+    }
+
+    #[test]
+    fn test_mecard() {
+        assert_eq!(
+            contact(MECARD),
+            Some(Contact {
+                name: Some("John Doe".into()),
+                organization: None,
+                phones: vec!["+15550100".into(), "+15550199".into()],
+                emails: vec!["john@example.com".into()],
+                urls: vec!["https://example.com".into()],
+            })
+        );
+        assert_eq!(contact("MECARD:;;"), Some(Contact::default()));
+        assert_eq!(contact("BEGIN:VEVENT"), None);
+        assert_eq!(contact("hello"), None);
+    }
+
+    #[test]
+    fn test_contact_display() {
+        assert_eq!(
+            contact(MECARD).unwrap().to_string(),
+            "Contact: John Doe\nPhone: +15550100\nPhone: +15550199\n\
+             Email: john@example.com\nURL: https://example.com"
+        );
+    }
+
+    #[test]
+    fn test_wifi_display() {
+        let wifi = wifi("WIFI:T:WPA;S:home;P:secret;H:true;;").unwrap();
+        assert_eq!(
+            wifi.to_string(),
+            "WiFi network: home (hidden)\nSecurity: WPA\nPassword: secret"
+        );
+    }
+}
@@ -1,36 +1,54 @@
 use log::LevelFilter;
 pub use log::{debug, error, info, trace, warn};
 use std::env;
+use std::fs::{File, OpenOptions};
+use std::io::{self, IsTerminal, Write};
+use std::path::Path;
 
-fn do_init(level: Option<LevelFilter>) {
+/// Set up logging at `level`, or as `RUST_LOG` says when `None`.
+///
+/// Records also go to `log_file` when given, and to stderr only if `console`.
+pub fn do_init(level: Option<LevelFilter>, log_file: Option<File>, console: bool) {
     use chrono::Local;
-    use env_logger::{fmt::style::{Style, Reset}, Builder};
-    use std::io::Write;
+    use env_logger::{fmt::style::Style, Builder, Target, WriteStyle};
 
+    // no escape codes in the file
+    let color = log_file.is_none() && use_color();
     let mut logger = Builder::from_default_env();
-    logger.format(|buf, record| {
-        let level_style = buf.default_level_style(record.level());
-        let dim = Style::new().dimmed();
+    if !color {
+        logger.write_style(WriteStyle::Never);
+    }
+    if log_file.is_some() || !console {
+        logger.target(Target::Pipe(Box::new(Tee {
+            console,
+            file: log_file,
+        })));
+    }
+    logger.format(move |buf, record| {
+        // plain styles render as nothing, keeping the layout the same
+        let (level_style, dim) = if color {
+            (buf.default_level_style(record.level()), Style::new().dimmed())
+        } else {
+            (Style::new(), Style::new())
+        };
 
         writeln!(
             buf,
-            "{}{}{}{}{}{} {}{: <5}{}{}{}{} {}",
+            "{}[{}{}{}{} {}{: <5}{}{}]{} {}",
 
             dim.render(),
-            "[",
-            Reset.render(),
+            dim.render_reset(),
 
             dim.render(),
             Local::now().format("%Y-%m-%d %H:%M:%S%.3f"),
-            Reset.render(),
+            dim.render_reset(),
 
             level_style.render(),
             record.level(),
-            Reset.render(),
+            level_style.render_reset(),
 
             dim.render(),
-            "]",
-            Reset.render(),
+            dim.render_reset(),
 
             record.args()
         )
@@ -43,7 +61,49 @@ fn do_init(level: Option<LevelFilter>) {
     logger.init()
 }
 
-pub fn init_logger() {
+/// Open `path` for appending log records.
+pub fn open_log_file(path: &Path) -> io::Result<File> {
+    OpenOptions::new().create(true).append(true).open(path)
+}
+
+/// Writes records to stderr and/or a file.
+struct Tee {
+    console: bool,
+    file: Option<File>,
+}
+
+impl Write for Tee {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        if self.console {
+            io::stderr().write_all(buf)?;
+        }
+        if let Some(file) = &mut self.file {
+            file.write_all(buf)?;
+        }
+        Ok(buf.len())
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        if let Some(file) = &mut self.file {
+            file.flush()?;
+        }
+        io::stderr().flush()
+    }
+}
+
+/// Color only for a terminal, and not when `NO_COLOR` is set, see no-color.org.
+fn use_color() -> bool {
+    let no_color = env::var_os("NO_COLOR").is_some_and(|v| !v.is_empty());
+    !no_color && io::stderr().is_terminal()
+}
+
+/// Set up logging at `level` if given, else as `RUST_LOG` says, else at the
+/// default for the build.
+pub fn init_logger(level: Option<LevelFilter>, log_file: Option<File>, console: bool) {
+    if level.is_some() {
+        return do_init(level, log_file, console);
+    }
+
     #[cfg(debug_assertions)]
     let level = LevelFilter::Debug;
 
@@ -51,7 +111,7 @@ pub fn init_logger() {
     let level = LevelFilter::Info;
 
     match env::var_os("RUST_LOG") {
-        Some(_) => do_init(None),
-        None => do_init(Some(level)),
+        Some(_) => do_init(None, log_file, console),
+        None => do_init(Some(level), log_file, console),
     }
 }
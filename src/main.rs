@@ -1,32 +1,676 @@
 mod capture;
+mod cli;
+mod clipboard;
+mod config;
+mod decode;
 mod logger;
+mod notify;
+mod output;
+mod outputs;
 mod selection;
+mod service;
+mod state;
+mod xdg;
 
-use anyhow::{anyhow, Result};
-use image::{RgbaImage, DynamicImage};
+use anyhow::{anyhow, Context, Result};
+use chrono::{DateTime, Local, SecondsFormat};
+use serde::Serialize;
+use std::io;
+use std::path::Path;
+use std::process::ExitCode;
+use std::thread;
+use std::time::{Duration, Instant};
+use wayland_client::ConnectError;
 
-use crate::selection::wait_for_selection;
+use crate::capture::{Backend, CaptureOptions, RawCaptured};
+use crate::decode::{decode_frames, decode_image, DecodeOptions, DecodeResult, Payload};
+use crate::logger::{debug, error, info, warn};
+use crate::selection::{
+    covered_by, output_region, wait_for_selection_with, watch_selections, Area, Background, Cancelled, Region, SelectionConfig,
+    TimedOut,
+};
 
-fn main() -> Result<()> {
-    logger::init_logger();
+/// Exit code when nothing was decoded, or selected before the timeout, errors
+/// exit with 1 and invalid arguments with 2.
+const EXIT_NOT_FOUND: u8 = 3;
+/// Exit code when the user cancelled the selection.
+const EXIT_CANCELLED: u8 = 4;
 
-    // select area from screen
-    let area = wait_for_selection()?;
+fn main() -> Result<ExitCode> {
+    let args = config::parse_args()?;
+    let log_file = args
+        .log_file
+        .as_deref()
+        .map(|path| {
+            logger::open_log_file(path)
+                .with_context(|| format!("failed to open log file {}", path.display()))
+        })
+        .transpose()?;
+    // with a log file, --quiet keeps the terminal clean instead of logging less
+    let console = !(args.quiet && log_file.is_some());
+    let level = if console { args.log_level() } else { None };
+    logger::init_logger(level, log_file, console);
 
-    // capture area
-    let captured = futures::executor::block_on(async {
-        capture::area(area.x(), area.y(), area.width(), area.height()).await
-    })?;
+    if args.list_outputs {
+        list_outputs(&args).map_err(explain_no_wayland)?;
+        return Ok(ExitCode::SUCCESS);
+    }
+
+    // no screen involved, don't touch wayland at all
+    if let Some(path) = &args.file {
+        return decode_file(&args, path);
+    }
+
+    if let Some(secs) = args.delay {
+        count_down(secs);
+    }
+
+    if args.client {
+        match futures::executor::block_on(service::scan()) {
+            Ok(Some(decoded)) => {
+                report(&args, &decoded, None)?;
+                return Ok(exit_code(&decoded));
+            }
+            Ok(None) => info!("no rq --serve running, scanning here"),
+            Err(err) => match no_selection(&err) {
+                Some(code) => {
+                    info!("{err}");
+                    return Ok(code);
+                }
+                None => return Err(err),
+            },
+        }
+    }
+
+    let backend = futures::executor::block_on(capture::detect_backend())?;
+    debug!("capturing with {backend:?}");
+
+    if args.serve {
+        futures::executor::block_on(service::serve(Box::new(move || serve_scan(&args, backend))))?;
+        return Ok(ExitCode::SUCCESS);
+    }
+
+    if args.watch {
+        return watch(&args, backend);
+    }
+    if args.pick_color {
+        return pick_color(&args, backend);
+    }
+    if let Some(count) = args.count {
+        return repeat(&args, backend, count);
+    }
+    if let Some(frames) = args.frames {
+        return sample_frames(&args, backend, frames);
+    }
+
+    let captured = match capture(&args, backend) {
+        Ok(captured) => captured,
+        Err(err) => match no_selection(&err) {
+            Some(code) => {
+                info!("{err}");
+                return Ok(code);
+            }
+            None => return Err(explain_no_wayland(err)),
+        },
+    };
 
+    if args.stdout_ppm {
+        let image = captured
+            .into_rgba_image()
+            .ok_or(anyhow!("failed to read image"))?;
+        output::write_ppm(&image, io::stdout().lock()).context("failed to write the capture")?;
+        return Ok(ExitCode::SUCCESS);
+    }
+
+    let decoded = decode_captured(&args, captured, None)?;
+    if decoded.is_empty() {
+        info!("no QR code found");
+    }
+
+    Ok(exit_code(&decoded))
+}
+
+/// Capture what the arguments ask for, letting the user select it if needed.
+fn capture(args: &cli::Args, backend: Backend) -> Result<RawCaptured> {
+    match &args.monitor {
+        Some(name) => outputs::find(name).and_then(|output| {
+            futures::executor::block_on(backend.screen(&output, capture_options(args)))
+        }),
+        None if args.active_screen => futures::executor::block_on(backend.active_screen(capture_options(args))),
+        None if args.workspace => futures::executor::block_on(backend.workspace(capture_options(args))),
+        None if args.primary => capture_primary(args, backend),
+        None if args.window => capture_window(args, backend),
+        None => match args.kwin_interactive {
+            Some(interactive) => {
+                futures::executor::block_on(backend.interactive(interactive.kind(), capture_options(args)))
+            }
+            None => capture_selection(args, backend),
+        },
+    }
+}
+
+/// One of the captures repeated with --count.
+struct Iteration {
+    /// from 1
+    index: u32,
+    time: DateTime<Local>,
+}
+impl Iteration {
+    fn timestamp(&self) -> String {
+        self.time.to_rfc3339_opts(SecondsFormat::Millis, false)
+    }
+}
+
+/// Capture and decode `count` times, --interval seconds apart.
+fn repeat(args: &cli::Args, backend: Backend, count: u32) -> Result<ExitCode> {
+    let started = Instant::now();
+    let mut found = false;
+    for index in 1..=count {
+        // counted from the start, slow captures don't make the next ones drift
+        let due = started + Duration::from_secs(args.interval) * (index - 1);
+        thread::sleep(due.saturating_duration_since(Instant::now()));
+
+        let iteration = Iteration {
+            index,
+            time: Local::now(),
+        };
+        let captured = capture(args, backend).map_err(explain_no_wayland)?;
+        let decoded = decode_captured(args, captured, Some(&iteration))?;
+        if decoded.is_empty() {
+            info!("no QR code found in capture {index}");
+        }
+        found |= !decoded.is_empty();
+    }
+    Ok(if found {
+        ExitCode::SUCCESS
+    } else {
+        ExitCode::from(EXIT_NOT_FOUND)
+    })
+}
+
+/// Capture the area selected once `frames` times in a row and report the
+/// codes found in any of the captures.
+fn sample_frames(args: &cli::Args, backend: Backend, frames: u32) -> Result<ExitCode> {
+    let area = match select_area(args, None) {
+        Ok(area) => area,
+        Err(err) => match no_selection(&err) {
+            Some(code) => {
+                info!("{err}");
+                return Ok(code);
+            }
+            None => return Err(explain_no_wayland(err)),
+        },
+    };
+    // all first, decoding in between would space them out
+    let mut scale = 1.;
+    let images = (0..frames)
+        .map(|_| {
+            let captured = capture_area(args, backend, None, &area)?;
+            scale = captured.scale;
+            captured
+                .into_rgba_image()
+                .ok_or(anyhow!("failed to read image"))
+        })
+        .collect::<Result<Vec<_>>>()?;
+    debug!("captured {frames} frames");
+
+    if let Some(path) = &args.output {
+        output::save_image(&images[0], path)?;
+        info!("saved the first capture to {}", path.display());
+    }
+
+    let decoded = decode_frames(&images, &decode_options(args, scale));
+    report(args, &decoded, None)?;
+    if decoded.is_empty() {
+        info!("no QR code found in {frames} captures");
+    }
+    Ok(exit_code(&decoded))
+}
+
+/// Save, decode and report one capture, the `iteration` of --count if any.
+fn decode_captured(
+    args: &cli::Args,
+    captured: RawCaptured,
+    iteration: Option<&Iteration>,
+) -> Result<Vec<DecodeResult>> {
+    let decoded = save_and_decode(args, captured)?;
+    report(args, &decoded, iteration)?;
+    Ok(decoded)
+}
+
+/// Decode one capture, saving it as --output and --output-dir ask.
+fn save_and_decode(args: &cli::Args, captured: RawCaptured) -> Result<Vec<DecodeResult>> {
     // read image
-    let image = RgbaImage::from_vec(captured.width, captured.height, captured.buf)
+    debug!(
+        "captured {}x{} at scale {}",
+        captured.width, captured.height, captured.scale
+    );
+    let scale = captured.scale;
+    let image = captured
+        .into_rgba_image()
+        .ok_or(anyhow!("failed to read image"))?;
+
+    // before decoding, so a capture that doesn't decode can be looked at
+    if let Some(path) = &args.output {
+        output::save_image(&image, path)?;
+        info!("saved capture to {}", path.display());
+    }
+
+    let decoded = decode_image(&image, &decode_options(args, scale));
+
+    if let Some(dir) = &args.output_dir {
+        let payload = decoded
+            .first()
+            .filter(|_| args.name_with_payload)
+            .map(|result| result.text.as_str());
+        let path = output::save_to_dir(&image, dir, payload)?;
+        info!("saved capture to {}", path.display());
+    }
+
+    Ok(decoded)
+}
+
+/// One scan asked for by `rq --client`, selecting like a plain run does.
+fn serve_scan(args: &cli::Args, backend: Backend) -> Result<Vec<DecodeResult>> {
+    let captured = match capture(args, backend) {
+        Ok(captured) => captured,
+        Err(err) if err.is::<TimedOut>() => {
+            info!("{err}");
+            return Ok(Vec::new());
+        }
+        Err(err) => return Err(err),
+    };
+    let decoded = save_and_decode(args, captured)?;
+    info!("scanned {} codes", decoded.len());
+    Ok(decoded)
+}
+
+/// Decode every region selected until the user presses Escape.
+fn watch(args: &cli::Args, backend: Backend) -> Result<ExitCode> {
+    let frozen = freeze(args, backend)?;
+    let mut found = false;
+    let watched = watch_selections(selection_config(args, frozen.as_ref())?, |area| {
+        let decoded = capture_area(args, backend, frozen.as_ref(), &area)
+            .and_then(|captured| decode_captured(args, captured, None));
+        match decoded {
+            Ok(decoded) if decoded.is_empty() => info!("no QR code found"),
+            Ok(_) => found = true,
+            // e.g. a capture denied by the compositor, the next one may work
+            Err(err) => error!("{err:#}"),
+        }
+        Ok(())
+    });
+    if let Err(err) = watched {
+        // running out of time ends watching like Escape does
+        if !err.is::<TimedOut>() {
+            return Err(explain_no_wayland(err));
+        }
+        info!("{err}");
+    }
+    Ok(if found {
+        ExitCode::SUCCESS
+    } else {
+        ExitCode::from(EXIT_NOT_FOUND)
+    })
+}
+
+/// Print the color of the pixel clicked on the frozen screen.
+fn pick_color(args: &cli::Args, backend: Backend) -> Result<ExitCode> {
+    let frozen = freeze(args, backend)?.ok_or(anyhow!("picking a color needs --freeze"))?;
+    let area = match wait_for_selection_with(selection_config(args, Some(&frozen))?) {
+        Ok(area) => area,
+        Err(err) => match no_selection(&err) {
+            Some(code) => {
+                info!("{err}");
+                return Ok(code);
+            }
+            None => return Err(explain_no_wayland(err)),
+        },
+    };
+    let image = frozen
+        .crop_workspace(workspace_origin(), area.logical)?
+        .into_rgba_image()
         .ok_or(anyhow!("failed to read image"))?;
-    let luma = DynamicImage::from(image).to_luma8();
+    // the top-left one of the pixels making up the logical one picked
+    let image::Rgba([r, g, b, a]) = *image
+        .get_pixel_checked(0, 0)
+        .ok_or(anyhow!("picked pixel is outside of the capture"))?;
+    let color = format!("#{r:02x}{g:02x}{b:02x}{a:02x}");
+
+    if args.json {
+        println!("{}", serde_json::json!({ "color": color }));
+    } else {
+        println!("{color}");
+    }
+    if args.copy {
+        info!("copied to clipboard, waiting for another selection to take over");
+        clipboard::copy(&color)?;
+    }
+    Ok(ExitCode::SUCCESS)
+}
+
+fn exit_code(decoded: &[DecodeResult]) -> ExitCode {
+    if decoded.is_empty() {
+        ExitCode::from(EXIT_NOT_FOUND)
+    } else {
+        ExitCode::SUCCESS
+    }
+}
+
+/// The exit code when `err` only means nothing was selected, which is not
+/// an error: the user cancelled or the timeout ran out.
+fn no_selection(err: &anyhow::Error) -> Option<ExitCode> {
+    if err.is::<Cancelled>() {
+        Some(ExitCode::from(EXIT_CANCELLED))
+    } else if err.is::<TimedOut>() {
+        Some(ExitCode::from(EXIT_NOT_FOUND))
+    } else {
+        None
+    }
+}
+
+/// Sleep for `secs` seconds, telling every second how many are left.
+fn count_down(secs: u64) {
+    for left in (1..=secs).rev() {
+        info!("capturing in {left}s");
+        thread::sleep(Duration::from_secs(1));
+    }
+}
+
+/// Say what to do instead when `err` comes from having no Wayland session,
+/// e.g. on a TTY, over SSH or under X11.
+fn explain_no_wayland(err: anyhow::Error) -> anyhow::Error {
+    if err.chain().any(|cause| cause.is::<ConnectError>()) {
+        err.context("rq needs a Wayland session to capture the screen, use --file to decode an image instead")
+    } else {
+        err
+    }
+}
+
+/// Capture the region given on the command line or selected by the user.
+fn capture_selection(args: &cli::Args, backend: Backend) -> Result<RawCaptured> {
+    let frozen = freeze(args, backend)?;
+    let area = select_area(args, frozen.as_ref())?;
+    capture_area(args, backend, frozen.as_ref(), &area)
+}
+
+/// The region given on the command line, or else selected by the user on
+/// the overlay, showing `frozen` if given.
+fn select_area(args: &cli::Args, frozen: Option<&RawCaptured>) -> Result<Area> {
+    let preset = args.region.or_else(|| args.last.then(last_region).flatten());
+    match preset {
+        Some(region) => Ok(Area::single(region)),
+        None => wait_for_selection_with(selection_config(args, frozen)?),
+    }
+}
+
+/// Capture the output the user is on, falling back to selecting when there is
+/// no telling which one that is.
+fn capture_primary(args: &cli::Args, backend: Backend) -> Result<RawCaptured> {
+    if backend == Backend::KWin {
+        match futures::executor::block_on(backend.active_screen(capture_options(args))) {
+            Ok(captured) => return Ok(captured),
+            Err(err) => warn!("failed to capture the active output, select a region: {err:#}"),
+        }
+    } else {
+        // only KWin tells which output is active
+        match outputs::list() {
+            Ok(outputs) if outputs.len() == 1 => {
+                return futures::executor::block_on(backend.screen(&outputs[0], capture_options(args)));
+            }
+            Ok(_) => warn!("no telling the primary output with {backend:?}, select a region"),
+            Err(err) => warn!("failed to list outputs, select a region: {err:#}"),
+        }
+    }
+    capture_selection(args, backend)
+}
+
+/// Capture the window the user clicks on the overlay, or the output where
+/// there is no telling the windows apart.
+fn capture_window(args: &cli::Args, backend: Backend) -> Result<RawCaptured> {
+    let windows = if backend == Backend::KWin {
+        futures::executor::block_on(backend.windows()).unwrap_or_else(|err| {
+            warn!("failed to list KWin's windows, click an output instead: {err:#}");
+            Vec::new()
+        })
+    } else {
+        info!("only KWin tells where windows are, click an output instead");
+        Vec::new()
+    };
+    let config = SelectionConfig {
+        windows: Some(windows.iter().map(|window| window.region).collect()),
+        ..selection_config(args, None)?
+    };
+    let area = wait_for_selection_with(config)?;
+    match area.window.and_then(|i| windows.get(i)) {
+        Some(window) => {
+            futures::executor::block_on(backend.window(&window.handle, capture_options(args)))
+        }
+        None => capture_area(args, backend, None, &area),
+    }
+}
+
+/// With --freeze, what is on screen now, before the overlay goes up.
+fn freeze(args: &cli::Args, backend: Backend) -> Result<Option<RawCaptured>> {
+    args.freeze
+        .then(|| futures::executor::block_on(backend.workspace(capture_options(args))))
+        .transpose()
+}
+
+/// How the overlay looks and behaves, showing `frozen` if given.
+fn selection_config(args: &cli::Args, frozen: Option<&RawCaptured>) -> Result<SelectionConfig> {
+    let background = frozen
+        .map(|frozen| -> Result<Background> {
+            let image = frozen.as_rgba_image().ok_or(anyhow!("failed to read image"))?;
+            // not every backend reports a scale
+            let scale = if frozen.scale > 0. { frozen.scale } else { 1. };
+            Ok(Background { image, scale })
+        })
+        .transpose()?;
+    Ok(SelectionConfig {
+        two_click: args.two_click,
+        dim_color: args.dim_color,
+        guide_color: args.guide_color,
+        border_color: args.border_color,
+        border_width: args.border_width,
+        background,
+        fps: args.fps,
+        magnifier: args.magnifier,
+        aspect: args.aspect,
+        timeout: args.timeout.map(Duration::from_secs),
+        keyboard: args.overlay_keyboard,
+        pick: args.pick_color,
+        windows: None,
+        right_click_cancels: true,
+    })
+}
+
+/// Capture `area`, or cut it out of the `frozen` screen.
+fn capture_area(
+    args: &cli::Args,
+    backend: Backend,
+    frozen: Option<&RawCaptured>,
+    area: &Area,
+) -> Result<RawCaptured> {
+    let captured = match frozen {
+        Some(frozen) => frozen.crop_workspace(workspace_origin(), area.logical)?,
+        // one capture of everything beats one per output
+        None if !area.pieces.is_empty() && backend == Backend::KWin => {
+            let workspace = futures::executor::block_on(backend.workspace(capture_options(args)))?;
+            workspace.crop_workspace(workspace_origin(), area.logical)?
+        }
+        None => futures::executor::block_on(backend.capture(area, capture_options(args)))?,
+    };
+
+    Ok(captured)
+}
+
+/// Top-left corner of the workspace, where captures of all of it start.
+fn workspace_origin() -> (i32, i32) {
+    match outputs::workspace() {
+        Ok(workspace) => workspace.map_or((0, 0), |region| (region.x(), region.y())),
+        Err(err) => {
+            warn!("failed to list outputs, assuming the workspace starts at 0,0: {err:#}");
+            (0, 0)
+        }
+    }
+}
+
+/// The region confirmed last time, unless the outputs changed since and no
+/// longer cover it.
+fn last_region() -> Option<Region> {
+    let Some(region) = state::last_region() else {
+        info!("no previous selection, select a region");
+        return None;
+    };
+    let outputs = match outputs::list() {
+        Ok(outputs) => outputs.iter().filter_map(output_region).collect::<Vec<_>>(),
+        Err(err) => {
+            warn!("failed to list outputs: {err:#}");
+            return None;
+        }
+    };
+    if !covered_by(region, &outputs) {
+        info!("previous selection is off screen now, select a region");
+        return None;
+    }
+    Some(region)
+}
+
+fn list_outputs(args: &cli::Args) -> Result<()> {
+    let outputs = outputs::list()?
+        .iter()
+        .map(outputs::Summary::new)
+        .collect::<Vec<_>>();
+    if args.json {
+        let output = JsonOutputs { outputs: &outputs };
+        println!("{}", serde_json::to_string(&output)?);
+    } else {
+        for output in outputs {
+            println!("{output}");
+        }
+    }
+    Ok(())
+}
+
+fn decode_file(args: &cli::Args, path: &Path) -> Result<ExitCode> {
+    let image = image::open(path)
+        .with_context(|| format!("failed to read image {}", path.display()))?
+        .to_rgba8();
+
+    let decoded = decode_image(&image, &decode_options(args, 1.));
+    report(args, &decoded, None)?;
+    if decoded.is_empty() {
+        info!("no QR code found in {}", path.display());
+    }
+
+    Ok(exit_code(&decoded))
+}
+
+fn capture_options(args: &cli::Args) -> Option<CaptureOptions> {
+    Some(CaptureOptions {
+        native_resolution: !args.logical,
+        include_cursor: args.cursor,
+        // the other captures have no window frame to keep
+        include_decoration: args.decoration
+            && (args.window || args.kwin_interactive == Some(cli::Interactive::Window)),
+        memfd: args.memfd,
+    })
+}
+
+/// `scale` is image pixels per logical pixel of the screen, 1 for files.
+fn decode_options(args: &cli::Args, scale: f64) -> DecodeOptions {
+    DecodeOptions {
+        upscale: !args.no_upscale,
+        formats: args.format.formats(),
+        scale,
+        invert: args.invert,
+        ..Default::default()
+    }
+}
+
+#[derive(Serialize)]
+struct JsonOutput<'a> {
+    /// with --count
+    #[serde(skip_serializing_if = "Option::is_none")]
+    iteration: Option<u32>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    timestamp: Option<String>,
+    results: Vec<JsonResult<'a>>,
+}
+
+#[derive(Serialize)]
+struct JsonOutputs<'a> {
+    outputs: &'a [outputs::Summary],
+}
+
+#[derive(Serialize)]
+struct JsonResult<'a> {
+    text: &'a str,
+    format: &'static str,
+    bounds: JsonBox,
+    /// e.g. `"wifi": {"ssid": ...}` for codes in a known scheme
+    #[serde(flatten)]
+    payload: Option<Payload>,
+}
+
+/// Where a code is in the capture, in its pixels.
+#[derive(Serialize)]
+struct JsonBox {
+    x: i32,
+    y: i32,
+    width: u32,
+    height: u32,
+}
+
+/// Hand the decoded payloads to the user, saying which `iteration` of
+/// --count they are from.
+fn report(args: &cli::Args, decoded: &[DecodeResult], iteration: Option<&Iteration>) -> Result<()> {
+    if args.json {
+        let output = JsonOutput {
+            iteration: iteration.map(|it| it.index),
+            timestamp: iteration.map(Iteration::timestamp),
+            results: decoded
+                .iter()
+                .map(|result| JsonResult {
+                    text: &result.text,
+                    format: result.format.name(),
+                    bounds: {
+                        let (x, y, width, height) = result.bounding_box();
+                        JsonBox {
+                            x,
+                            y,
+                            width,
+                            height,
+                        }
+                    },
+                    payload: result.payload(),
+                })
+                .collect(),
+        };
+        println!("{}", serde_json::to_string(&output)?);
+    } else {
+        let prefix = iteration
+            .filter(|_| !args.raw)
+            .map(|it| format!("{}\t{}\t", it.index, it.timestamp()))
+            .unwrap_or_default();
+        for result in decoded {
+            match result.payload().filter(|_| !args.raw) {
+                Some(payload) => println!("{prefix}{payload}"),
+                None => println!("{prefix}{}", result.text),
+            }
+        }
+    }
+
+    if args.notify {
+        let texts = decoded.iter().map(|r| r.text.clone()).collect::<Vec<_>>();
+        futures::executor::block_on(notify::decoded(&texts))?;
+    }
 
-    let mut img = rqrr::PreparedImage::prepare(luma);
-    for grid in img.detect_grids() {
-        let (_meta, content) = grid.decode()?;
-        println!("{content}");
+    // last, this blocks until the clipboard is taken over
+    if let Some(result) = decoded.first().filter(|_| args.copy) {
+        info!("copied to clipboard, waiting for another selection to take over");
+        clipboard::copy(&result.text)?;
     }
 
     Ok(())
@@ -0,0 +1,87 @@
+use anyhow::Result;
+use std::collections::HashMap;
+use zbus::{proxy, zvariant::Value, Connection};
+
+const APP_NAME: &str = "rq";
+const SUMMARY_MAX_CHARS: usize = 64;
+
+#[proxy(
+    default_service = "org.freedesktop.Notifications",
+    interface = "org.freedesktop.Notifications",
+    default_path = "/org/freedesktop/Notifications"
+)]
+trait Notifications {
+    #[allow(clippy::too_many_arguments)]
+    fn notify(
+        &self,
+        app_name: &str,
+        replaces_id: u32,
+        app_icon: &str,
+        summary: &str,
+        body: &str,
+        actions: &[&str],
+        hints: HashMap<&str, &Value<'_>>,
+        expire_timeout: i32,
+    ) -> zbus::Result<u32>;
+}
+
+async fn send(summary: &str, body: &str) -> Result<()> {
+    let conn = Connection::session().await?;
+    let proxy = NotificationsProxy::new(&conn).await?;
+    proxy
+        .notify(APP_NAME, 0, "", summary, &escape(body), &[], HashMap::new(), -1)
+        .await?;
+    Ok(())
+}
+
+/// Show the decoded payloads, or that nothing was found, as a desktop notification.
+pub async fn decoded(decoded: &[String]) -> Result<()> {
+    match decoded {
+        [] => send("No QR code found", "").await,
+        [content] => send(&truncate(content), content).await,
+        [first, ..] => {
+            let summary = format!("{} (+{} more)", truncate(first), decoded.len() - 1);
+            send(&summary, &decoded.join("\n")).await
+        }
+    }
+}
+
+/// The body may be interpreted as markup by the notification server.
+fn escape(s: &str) -> String {
+    s.replace('&', "&amp;")
+        .replace('<', "&lt;")
+        .replace('>', "&gt;")
+}
+
+fn truncate(s: &str) -> String {
+    let first_line = s.lines().next().unwrap_or_default();
+    if first_line.chars().count() <= SUMMARY_MAX_CHARS && first_line.len() == s.len() {
+        return s.to_owned();
+    }
+    let mut out = first_line
+        .chars()
+        .take(SUMMARY_MAX_CHARS - 1)
+        .collect::<String>();
+    out.push('…');
+    out
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_truncate() {
+        assert_eq!(truncate("hello"), "hello");
+        assert_eq!(truncate("line one\nline two"), "line one…");
+        let long = "x".repeat(100);
+        let summary = truncate(&long);
+        assert_eq!(summary.chars().count(), SUMMARY_MAX_CHARS);
+        assert!(summary.ends_with('…'));
+    }
+
+    #[test]
+    fn test_escape() {
+        assert_eq!(escape("a<b>&c"), "a&lt;b&gt;&amp;c");
+    }
+}
@@ -0,0 +1,140 @@
+use anyhow::{Context, Result};
+use chrono::Local;
+use image::{DynamicImage, ImageFormat, RgbaImage};
+use std::{
+    fs,
+    io::{self, Write},
+    path::{Path, PathBuf},
+};
+
+const PAYLOAD_MAX_LEN: usize = 48;
+
+/// Save `image` to `path`, the format is inferred from the extension.
+pub fn save_image(image: &RgbaImage, path: &Path) -> Result<()> {
+    let saved = match ImageFormat::from_path(path) {
+        // jpeg has no alpha channel
+        Ok(ImageFormat::Jpeg) => DynamicImage::ImageRgba8(image.clone())
+            .to_rgb8()
+            .save(path),
+        _ => image.save(path),
+    };
+    saved.with_context(|| format!("failed to save image to {}", path.display()))
+}
+
+/// Save `image` into `dir` under a fresh timestamped name, e.g.
+/// `rq-20240101-120000.png` or `rq-20240101-120000-2-hello_world.png`.
+///
+/// The directory is created when missing and existing files are never
+/// overwritten.
+pub fn save_to_dir(image: &RgbaImage, dir: &Path, payload: Option<&str>) -> Result<PathBuf> {
+    fs::create_dir_all(dir)
+        .with_context(|| format!("failed to create directory {}", dir.display()))?;
+    let stem = Local::now().format("rq-%Y%m%d-%H%M%S").to_string();
+    let path = unique_path(dir, &stem, payload.map(sanitize).as_deref());
+    save_image(image, &path)?;
+    Ok(path)
+}
+
+/// Write `image` to `out` as a binary PPM: the header `P6\n<width>
+/// <height>\n255\n` in ASCII, then 3 bytes of red, green and blue for each
+/// pixel, row by row from the top-left. Alpha is dropped.
+pub fn write_ppm(image: &RgbaImage, out: impl Write) -> io::Result<()> {
+    let mut out = io::BufWriter::new(out);
+    write!(out, "P6\n{} {}\n255\n", image.width(), image.height())?;
+    for pixel in image.pixels() {
+        out.write_all(&pixel.0[..3])?;
+    }
+    out.flush()
+}
+
+fn unique_path(dir: &Path, stem: &str, suffix: Option<&str>) -> PathBuf {
+    let name = |n: u32| {
+        let mut name = stem.to_owned();
+        if n > 0 {
+            name.push_str(&format!("-{n}"));
+        }
+        if let Some(suffix) = suffix.filter(|s| !s.is_empty()) {
+            name.push('-');
+            name.push_str(suffix);
+        }
+        name.push_str(".png");
+        dir.join(name)
+    };
+    (0..)
+        .map(name)
+        .find(|path| !path.exists())
+        .expect("ran out of file names")
+}
+
+/// Turn a decoded payload into something safe to use in a file name.
+fn sanitize(payload: &str) -> String {
+    let mut out = String::new();
+    for c in payload.chars() {
+        if out.len() >= PAYLOAD_MAX_LEN {
+            break;
+        }
+        if c.is_ascii_alphanumeric() || c == '-' || c == '.' {
+            out.push(c);
+        } else if !out.ends_with('_') {
+            out.push('_');
+        }
+    }
+    out.trim_matches(|c| c == '_' || c == '.').to_owned()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_sanitize() {
+        assert_eq!(sanitize("https://example.com/a?b=c"), "https_example.com_a_b_c");
+        assert_eq!(sanitize("../../etc/passwd"), "etc_passwd");
+        assert_eq!(sanitize("  "), "");
+        assert_eq!(sanitize(&"x".repeat(100)).len(), PAYLOAD_MAX_LEN);
+    }
+
+    #[test]
+    fn test_save_image() {
+        let dir = std::env::temp_dir().join(format!("rq-save-test-{}", std::process::id()));
+        fs::create_dir_all(&dir).unwrap();
+        let image = RgbaImage::from_pixel(4, 3, image::Rgba([10, 20, 30, 255]));
+
+        for name in ["a.png", "a.jpg", "a.jpeg", "a.webp"] {
+            let path = dir.join(name);
+            save_image(&image, &path).unwrap();
+            let saved = image::open(&path).unwrap();
+            assert_eq!((saved.width(), saved.height()), (4, 3), "{name}");
+        }
+        assert!(save_image(&image, &dir.join("a.unknown")).is_err());
+
+        fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn test_write_ppm() {
+        let mut image = RgbaImage::from_pixel(2, 1, image::Rgba([10, 20, 30, 0]));
+        image.put_pixel(1, 0, image::Rgba([40, 50, 60, 255]));
+        let mut out = Vec::new();
+        write_ppm(&image, &mut out).unwrap();
+        assert_eq!(out, b"P6\n2 1\n255\n\x0a\x14\x1e\x28\x32\x3c");
+    }
+
+    #[test]
+    fn test_unique_path() {
+        let dir = std::env::temp_dir().join(format!("rq-output-test-{}", std::process::id()));
+        let image = RgbaImage::new(1, 1);
+
+        let first = save_to_dir(&image, &dir, Some("hello world")).unwrap();
+        let second = unique_path(&dir, "rq", None);
+        fs::write(&second, b"").unwrap();
+        let third = unique_path(&dir, "rq", None);
+
+        assert!(first.exists());
+        assert!(first.to_string_lossy().ends_with("-hello_world.png"));
+        assert_eq!(second, dir.join("rq.png"));
+        assert_eq!(third, dir.join("rq-1.png"));
+
+        fs::remove_dir_all(&dir).unwrap();
+    }
+}
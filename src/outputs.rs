@@ -0,0 +1,163 @@
+//! Enumerate the outputs of the compositor, without showing anything.
+use anyhow::{anyhow, Result};
+use itertools::Itertools;
+use serde::Serialize;
+use smithay_client_toolkit::{
+    delegate_output, delegate_registry,
+    output::{OutputHandler, OutputInfo, OutputState},
+    registry::{ProvidesRegistryState, RegistryState},
+    registry_handlers,
+};
+use std::fmt;
+use wayland_client::{
+    globals::registry_queue_init,
+    protocol::wl_output::{Transform, WlOutput},
+    Connection, QueueHandle,
+};
+
+use crate::selection::{output_region, output_scale, Region};
+
+struct OutputsState {
+    registry_state: RegistryState,
+    output_state: OutputState,
+}
+
+/// Information about every output, in the order the compositor announced them.
+pub fn list() -> Result<Vec<OutputInfo>> {
+    let conn = Connection::connect_to_env()?;
+    let (globals, mut event_queue) = registry_queue_init::<OutputsState>(&conn)?;
+    let qh = event_queue.handle();
+
+    let mut state = OutputsState {
+        registry_state: RegistryState::new(&globals),
+        output_state: OutputState::new(&globals, &qh),
+    };
+    // outputs, then their xdg-output geometry
+    event_queue.roundtrip(&mut state)?;
+    event_queue.roundtrip(&mut state)?;
+
+    Ok(state
+        .output_state
+        .outputs()
+        .filter_map(|output| state.output_state.info(&output))
+        .collect())
+}
+
+/// The logical bounding box of all outputs, `None` without any.
+pub fn workspace() -> Result<Option<Region>> {
+    let regions = list()?.iter().filter_map(output_region).collect::<Vec<_>>();
+    let left = regions.iter().map(Region::left).min();
+    let top = regions.iter().map(Region::top).min();
+    let right = regions.iter().map(Region::right).max();
+    let bottom = regions.iter().map(Region::bottom).max();
+    Ok(left
+        .zip(top)
+        .zip(right.zip(bottom))
+        .and_then(|((left, top), (right, bottom))| Region::from_ltrb(left, top, right, bottom)))
+}
+
+/// The output called `name`, e.g. `DP-1`.
+pub fn find(name: &str) -> Result<OutputInfo> {
+    let outputs = list()?;
+    let names = outputs
+        .iter()
+        .filter_map(|info| info.name.as_deref())
+        .join(", ");
+    outputs
+        .into_iter()
+        .find(|info| info.name.as_deref() == Some(name))
+        .ok_or_else(|| anyhow!("no output named {name}, available outputs: {names}"))
+}
+
+/// What `--list-outputs` shows about an output.
+#[derive(Debug, PartialEq, Serialize)]
+pub struct Summary {
+    pub name: String,
+    pub x: i32,
+    pub y: i32,
+    pub width: u32,
+    pub height: u32,
+    pub scale: f64,
+    pub transform: &'static str,
+}
+impl Summary {
+    pub fn new(info: &OutputInfo) -> Self {
+        let (x, y, width, height) = output_region(info)
+            .map(|region| (region.x(), region.y(), region.width(), region.height()))
+            .unwrap_or_default();
+        Self {
+            name: info.name.clone().unwrap_or_default(),
+            x,
+            y,
+            width,
+            height,
+            scale: output_scale(info),
+            transform: transform_name(info.transform),
+        }
+    }
+}
+/// One line per output: `NAME X,Y WIDTHxHEIGHT SCALE TRANSFORM`.
+impl fmt::Display for Summary {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(
+            f,
+            "{} {},{} {}x{} {} {}",
+            self.name, self.x, self.y, self.width, self.height, self.scale, self.transform
+        )
+    }
+}
+
+fn transform_name(transform: Transform) -> &'static str {
+    match transform {
+        Transform::Normal => "normal",
+        Transform::_90 => "90",
+        Transform::_180 => "180",
+        Transform::_270 => "270",
+        Transform::Flipped => "flipped",
+        Transform::Flipped90 => "flipped-90",
+        Transform::Flipped180 => "flipped-180",
+        Transform::Flipped270 => "flipped-270",
+        _ => "unknown",
+    }
+}
+
+delegate_registry!(OutputsState);
+impl ProvidesRegistryState for OutputsState {
+    fn registry(&mut self) -> &mut RegistryState {
+        &mut self.registry_state
+    }
+    registry_handlers![OutputState];
+}
+
+delegate_output!(OutputsState);
+impl OutputHandler for OutputsState {
+    fn output_state(&mut self) -> &mut OutputState {
+        &mut self.output_state
+    }
+
+    fn new_output(&mut self, _conn: &Connection, _qh: &QueueHandle<Self>, _output: WlOutput) {}
+
+    fn update_output(&mut self, _conn: &Connection, _qh: &QueueHandle<Self>, _output: WlOutput) {}
+
+    fn output_destroyed(&mut self, _conn: &Connection, _qh: &QueueHandle<Self>, _output: WlOutput) {
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_summary_display() {
+        let summary = Summary {
+            name: "DP-1".into(),
+            x: -1920,
+            y: 0,
+            width: 1707,
+            height: 960,
+            scale: 1.5,
+            transform: transform_name(Transform::Flipped90),
+        };
+        assert_eq!(summary.to_string(), "DP-1 -1920,0 1707x960 1.5 flipped-90");
+    }
+}
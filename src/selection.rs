@@ -1,15 +1,20 @@
+mod entry;
+mod label;
+mod loupe;
+
 use anyhow::{anyhow, Result};
+use clap::ValueEnum;
 use smithay_client_toolkit::{
     compositor::{CompositorHandler, CompositorState},
     delegate_compositor, delegate_keyboard, delegate_layer, delegate_output, delegate_pointer,
     delegate_registry, delegate_seat, delegate_shm,
-    output::{OutputHandler, OutputState},
+    output::{OutputHandler, OutputInfo, OutputState},
     registry::{ProvidesRegistryState, RegistryState},
     registry_handlers,
     seat::{
-        keyboard::{KeyEvent, KeyboardHandler, Keysym},
+        keyboard::{KeyEvent, KeyboardHandler, Keysym, Modifiers},
         pointer::{
-            PointerEvent, PointerEventKind, PointerHandler, ThemeSpec, ThemedPointer, BTN_LEFT, CursorIcon,
+            PointerEvent, PointerEventKind, PointerHandler, ThemeSpec, ThemedPointer, BTN_LEFT, BTN_RIGHT, CursorIcon,
         },
         Capability, SeatHandler, SeatState,
     },
@@ -20,10 +25,19 @@ use smithay_client_toolkit::{
         },
         WaylandSurface,
     },
-    shm::{slot::SlotPool, Shm, ShmHandler},
+    shm::{
+        slot::{Buffer, SlotPool},
+        Shm, ShmHandler,
+    },
+};
+use image::RgbaImage;
+use std::{
+    collections::HashMap,
+    fmt, io,
+    os::fd::AsRawFd,
+    time::{Duration, Instant},
 };
-use std::time::Instant;
-use tiny_skia::{Color, IntRect, Pixmap};
+use tiny_skia::{Color, ColorU8, IntRect, Pixmap};
 use wayland_client::{
     globals::registry_queue_init,
     protocol::{
@@ -34,9 +48,14 @@ use wayland_client::{
         wl_shm,
         wl_surface::WlSurface,
     },
-    Connection, Proxy, QueueHandle,
+    backend::WaylandError,
+    Connection, DispatchError, EventQueue, Proxy, QueueHandle,
 };
 
+use crate::logger::{debug, error, trace, warn};
+use crate::state;
+use entry::Entry;
+
 #[derive(Default, Debug, Clone, Copy, PartialEq, Eq)]
 struct Pos {
     x: i32,
@@ -45,16 +64,109 @@ struct Pos {
 
 pub type Region = IntRect;
 
+/// how far the pointer may move between press and release for a click
+const CLICK_THRESHOLD: i32 = 3;
+
+/// smallest width and height of a selection worth capturing
+const MIN_SELECTION_SIZE: u32 = 3;
+
+/// how close to a corner or edge of the selection a press grabs it
+const HANDLE_RADIUS: i32 = 6;
+
+/// Whether a press and release at these positions is a click rather than a drag.
+fn is_click(pressed: Pos, released: Pos) -> bool {
+    (pressed.x - released.x).abs() <= CLICK_THRESHOLD
+        && (pressed.y - released.y).abs() <= CLICK_THRESHOLD
+}
+
 struct LayerContext {
     layer: LayerSurface,
+    output: WlOutput,
     region: Region,
+    scale: f64,        // physical pixels per logical pixel
+    buffer_scale: i32, // buffer pixels per logical pixel, as told by the compositor
     pixmap: Pixmap,
+    buffer: Option<Buffer>,     // shm buffer reused across frames
+    background: Option<Pixmap>, // frozen screen content, bgra
+    dirty: bool,         // content changed since the last draw
+    frame_pending: bool, // waiting for the compositor's frame callback
+    frame_interval: Option<Duration>, // least time between draws, from --fps
+    last_draw: Option<Instant>,
+}
+impl LayerContext {
+    /// Reallocate the pixmaps for `buffer_scale` buffer pixels per logical pixel.
+    fn set_buffer_scale(&mut self, buffer_scale: i32, background: Option<&Background>) {
+        let buffer_scale = buffer_scale.max(1);
+        let size = |v: u32| v * buffer_scale as u32;
+        let Some(pixmap) = Pixmap::new(size(self.region.width()), size(self.region.height()))
+        else {
+            error!("output is too large for a scale of {buffer_scale}");
+            return;
+        };
+        self.buffer_scale = buffer_scale;
+        self.pixmap = pixmap;
+        self.background = background.and_then(|bg| bg.pixmap(self.region, buffer_scale));
+        self.layer.wl_surface().set_buffer_scale(buffer_scale);
+        self.dirty = true;
+    }
+}
+
+/// A width to height ratio the selection is kept at.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Aspect {
+    pub width: u32,
+    pub height: u32,
+}
+impl Aspect {
+    /// The corner opposite `from` of the rectangle at this ratio reaching `to`
+    /// along one axis and covering it along the other.
+    fn constrain(self, from: Pos, to: Pos) -> Pos {
+        let (dx, dy) = ((to.x - from.x) as f64, (to.y - from.y) as f64);
+        if dx.abs() * self.height as f64 >= dy.abs() * self.width as f64 {
+            self.fit_width(from, to)
+        } else {
+            self.fit_height(from, to)
+        }
+    }
+
+    /// Keep the width between `from` and `to` and derive the height.
+    fn fit_width(self, from: Pos, to: Pos) -> Pos {
+        let dx = to.x - from.x;
+        let dy = (dx.abs() as f64 * self.height as f64 / self.width as f64).round() as i32;
+        let sign = if to.y < from.y { -1 } else { 1 };
+        Pos {
+            x: to.x,
+            y: from.y + sign * dy,
+        }
+    }
+
+    /// Keep the height between `from` and `to` and derive the width.
+    fn fit_height(self, from: Pos, to: Pos) -> Pos {
+        let dy = to.y - from.y;
+        let dx = (dy.abs() as f64 * self.width as f64 / self.height as f64).round() as i32;
+        let sign = if to.x < from.x { -1 } else { 1 };
+        Pos {
+            x: from.x + sign * dx,
+            y: to.y,
+        }
+    }
+}
+
+/// A corner or edge of a finished selection, grabbed to resize it.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+struct Handle {
+    /// side moved along each axis, `true` for right or bottom, `None` when
+    /// it stays put
+    x: Option<bool>,
+    y: Option<bool>,
 }
 
 #[derive(Default, Debug)]
 struct Selection {
     data: Option<(Pos, Pos)>, // (from, to)
     on: bool,
+    aspect: Option<Aspect>,
+    grab: Option<Pos>, // where the pointer was last while moving the whole selection
 }
 impl Selection {
     #[inline]
@@ -75,6 +187,21 @@ impl Selection {
         let data = self.data.get_or_insert(Default::default());
         data.1 = to;
     }
+    /// Move the moving corner to `to`, or as close as the aspect allows.
+    #[inline]
+    fn drag_to(&mut self, to: Pos) {
+        let to = match (self.aspect, self.from()) {
+            (Some(aspect), Some(from)) => aspect.constrain(from, to),
+            _ => to,
+        };
+        self.set_to(to);
+    }
+    /// Select exactly `from` to `to`, whatever the aspect.
+    #[inline]
+    pub fn select(&mut self, from: Pos, to: Pos) {
+        self.on = false;
+        self.data = Some((from, to));
+    }
     #[inline]
     pub fn has_value(&self) -> bool {
         self.data.is_some()
@@ -83,6 +210,7 @@ impl Selection {
     pub fn reset(&mut self) {
         self.on = false;
         self.data = None;
+        self.grab = None;
     }
     #[inline]
     pub fn begin(&mut self, pos: Pos) {
@@ -94,117 +222,875 @@ impl Selection {
     #[inline]
     pub fn update(&mut self, pos: Pos) {
         if self.on {
-            self.set_to(pos);
+            self.drag_to(pos);
+        } else if let (Some(last), Some((from, to))) = (self.grab, self.data.as_mut()) {
+            let (dx, dy) = (pos.x - last.x, pos.y - last.y);
+            for corner in [from, to] {
+                corner.x += dx;
+                corner.y += dy;
+            }
+            self.grab = Some(pos);
         }
     }
+    /// Start moving the finished selection along with the pointer, when
+    /// `pos` is inside it. A selection of the whole `output` under the
+    /// pointer is left alone, pressing there starts a new one.
+    #[inline]
+    pub fn grab(&mut self, pos: Pos, output: Region) -> bool {
+        let inside = !self.on
+            && self.to_region().is_some_and(|region| {
+                region != output
+                    && (region.left()..region.right()).contains(&pos.x)
+                    && (region.top()..region.bottom()).contains(&pos.y)
+            });
+        if inside {
+            self.grab = Some(pos);
+        }
+        inside
+    }
+    /// The corner or edge of the finished selection near `pos`.
+    #[inline]
+    pub fn handle_at(&self, pos: Pos) -> Option<Handle> {
+        if self.on {
+            return None;
+        }
+        let region = self.to_region()?;
+        let near = |v: i32, low: i32, high: i32| {
+            let (to_low, to_high) = ((v - low).abs(), (v - high).abs());
+            (to_low.min(to_high) <= HANDLE_RADIUS).then_some(to_high < to_low)
+        };
+        let within = |v: i32, low: i32, high: i32| {
+            (low - HANDLE_RADIUS..=high + HANDLE_RADIUS).contains(&v)
+        };
+        if !within(pos.x, region.left(), region.right())
+            || !within(pos.y, region.top(), region.bottom())
+        {
+            return None;
+        }
+        let handle = Handle {
+            x: near(pos.x, region.left(), region.right()),
+            y: near(pos.y, region.top(), region.bottom()),
+        };
+        (handle.x.is_some() || handle.y.is_some()).then_some(handle)
+    }
+    /// Turn the selection around so the `handle` side is the moving corner.
+    #[inline]
+    pub fn hold(&mut self, handle: Handle) {
+        let Some(region) = self.to_region() else {
+            return;
+        };
+        let (left, right) = (region.left(), region.right());
+        let (top, bottom) = (region.top(), region.bottom());
+        let (from_x, to_x) = if handle.x == Some(false) { (right, left) } else { (left, right) };
+        let (from_y, to_y) = if handle.y == Some(false) { (bottom, top) } else { (top, bottom) };
+        self.data = Some((Pos { x: from_x, y: from_y }, Pos { x: to_x, y: to_y }));
+    }
+    /// Move the sides of `handle` to `pos`, keeping the aspect if any.
+    #[inline]
+    pub fn resize(&mut self, handle: Handle, pos: Pos) {
+        let Some((from, to)) = self.data.as_mut() else {
+            return;
+        };
+        if handle.x.is_some() {
+            to.x = pos.x;
+        }
+        if handle.y.is_some() {
+            to.y = pos.y;
+        }
+        *to = match (self.aspect, handle.x, handle.y) {
+            (Some(aspect), Some(_), Some(_)) => aspect.constrain(*from, *to),
+            (Some(aspect), Some(_), None) => aspect.fit_width(*from, *to),
+            (Some(aspect), None, _) => aspect.fit_height(*from, *to),
+            (None, ..) => *to,
+        };
+    }
+    /// Stop moving the selection, `false` when it was not being moved.
+    #[inline]
+    pub fn release(&mut self) -> bool {
+        self.grab.take().is_some()
+    }
     #[inline]
     pub fn end(&mut self, pos: Pos) {
         if self.on {
             self.on = false;
-            self.set_to(pos);
+            self.drag_to(pos);
+        }
+    }
+    #[inline]
+    pub fn nudge(&mut self, dx: i32, dy: i32) {
+        if let Some((from, to)) = self.data.as_mut() {
+            to.x += dx;
+            to.y += dy;
+            // follow the axis that moved
+            *to = match self.aspect {
+                Some(aspect) if dx != 0 => aspect.fit_width(*from, *to),
+                Some(aspect) => aspect.fit_height(*from, *to),
+                None => *to,
+            };
         }
     }
+    /// The selected region, `None` when empty or too large for a `Region`.
     #[inline]
     pub fn to_region(&self) -> Option<Region> {
-        self.data
-            .map(|(from, to)| {
-                let x = from.x.min(to.x);
-                let y = from.y.min(to.y);
-                let w = (from.x - to.x).abs() as u32;
-                let h = (from.y - to.y).abs() as u32;
-                Region::from_xywh(x, y, w, h)
+        self.data.and_then(|(from, to)| {
+            let x = from.x.min(to.x);
+            let y = from.y.min(to.y);
+            // corners far apart are more than an i32 apart
+            let span = |a: i32, b: i32| u32::try_from((a as i64 - b as i64).unsigned_abs()).ok();
+            Region::from_xywh(x, y, span(from.x, to.x)?, span(from.y, to.y)?)
+        })
+    }
+    /// The selected region, unless it is too thin to hold anything.
+    #[inline]
+    pub fn usable_region(&self) -> Option<Region> {
+        self.to_region().filter(|region| {
+            region.width() >= MIN_SELECTION_SIZE && region.height() >= MIN_SELECTION_SIZE
+        })
+    }
+}
+
+/// `size` of an output's mode once `transform` is applied, modes are given
+/// before it so rotating by 90 or 270 degrees swaps width and height.
+pub(crate) fn transformed_size(size: (i32, i32), transform: Transform) -> (i32, i32) {
+    match transform {
+        Transform::_90 | Transform::_270 | Transform::Flipped90 | Transform::Flipped270 => {
+            (size.1, size.0)
+        }
+        _ => size,
+    }
+}
+
+/// Allocate with `allocate` room for a buffer of each of `sizes` at once, or
+/// else as much as can be had, halving down to the largest buffer alone.
+///
+/// Buffers that don't fit grow the pool on demand, failing then only costs
+/// the frame.
+fn fit_pool<T, E: fmt::Display>(
+    sizes: &[usize],
+    mut allocate: impl FnMut(usize) -> Result<T, E>,
+) -> Result<T> {
+    let single = sizes.iter().copied().max().unwrap_or_default();
+    let mut size = sizes.iter().sum::<usize>();
+    loop {
+        match allocate(size) {
+            Ok(pool) => return Ok(pool),
+            Err(err) if size > single => {
+                warn!("failed to allocate {size} bytes for drawing, trying with less: {err}");
+                size = (size / 2).max(single);
+            }
+            Err(err) => return Err(anyhow!("failed to allocate {size} bytes for drawing: {err}")),
+        }
+    }
+}
+
+/// Whether `elapsed` since the last draw is enough to draw again. Frame
+/// callbacks don't arrive exactly on time, allow for some slack.
+fn frame_due(elapsed: Duration, interval: Duration) -> bool {
+    elapsed + interval / 4 >= interval
+}
+
+/// Physical pixels per logical pixel of an output.
+///
+/// Derived from the current mode so fractional scales (e.g. 1.5) are exact,
+/// `scale_factor` is only the integer scale rounded up.
+pub(crate) fn output_scale(info: &OutputInfo) -> f64 {
+    let mode = info.modes.iter().find(|mode| mode.current);
+    match (mode, info.logical_size) {
+        (Some(mode), Some((w, _))) if w > 0 => {
+            transformed_size(mode.dimensions, info.transform).0 as f64 / w as f64
+        }
+        _ => info.scale_factor as f64,
+    }
+}
+
+/// Position and size of an output in the global logical space.
+///
+/// Falls back to the transformed mode for compositors without xdg-output.
+pub(crate) fn output_region(info: &OutputInfo) -> Option<Region> {
+    let (x, y) = info.logical_position.unwrap_or(info.location);
+    let (w, h) = info.logical_size.or_else(|| {
+        let mode = info.modes.iter().find(|mode| mode.current)?;
+        let (w, h) = transformed_size(mode.dimensions, info.transform);
+        let scale = info.scale_factor.max(1);
+        Some((w / scale, h / scale))
+    })?;
+    Region::from_xywh(x, y, w as u32, h as u32)
+}
+
+/// A selection in the physical pixels used for captures.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Area {
+    /// the whole selection, scaled like the output containing its origin
+    pub region: Region,
+    /// the whole selection in logical pixels
+    pub logical: Region,
+    /// what to capture from each output covered, when there is more than one
+    pub pieces: Vec<Piece>,
+    /// the index of the window clicked, with `SelectionConfig::windows`
+    pub window: Option<usize>,
+}
+impl Area {
+    /// An area captured in one go.
+    pub fn single(region: Region) -> Self {
+        Self {
+            region,
+            logical: region,
+            pieces: Vec::new(),
+            window: None,
+        }
+    }
+}
+
+/// The part of an `Area` on one output.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct Piece {
+    /// what to capture, in the physical pixels of its output
+    pub source: Region,
+    /// where it goes, relative to the `Area`
+    pub target: Region,
+}
+
+/// Whether `outputs` cover every pixel of `region`, they don't overlap.
+pub(crate) fn covered_by(region: Region, outputs: &[Region]) -> bool {
+    let covered = outputs
+        .iter()
+        .filter_map(|output| region.intersect(output))
+        .map(|part| part.width() as u64 * part.height() as u64)
+        .sum::<u64>();
+    covered == region.width() as u64 * region.height() as u64
+}
+
+/// The box around the parts of `region` on any of the `outputs`, a drag
+/// leaving the overlay can end a little past its edge, or in a gap between
+/// outputs of different sizes.
+fn clamp_to(region: Region, outputs: &[Region]) -> Option<Region> {
+    let parts = outputs
+        .iter()
+        .filter_map(|output| region.intersect(output))
+        .collect::<Vec<_>>();
+    Region::from_ltrb(
+        parts.iter().map(|p| p.left()).min()?,
+        parts.iter().map(|p| p.top()).min()?,
+        parts.iter().map(|p| p.right()).max()?,
+        parts.iter().map(|p| p.bottom()).max()?,
+    )
+}
+
+/// Map a logical `region` into an `Area`, split along the `outputs`
+/// it covers, which are `(logical region, scale)` pairs.
+fn split(region: Region, outputs: &[(Region, f64)]) -> Option<Area> {
+    let physical = to_physical(region, outputs.iter().copied())?;
+    let scale = physical.width() as f64 / region.width() as f64;
+    let pieces = outputs
+        .iter()
+        .filter_map(|(output, output_scale)| {
+            let part = region.intersect(output)?;
+            let offset = Region::from_xywh(
+                part.x() - region.x(),
+                part.y() - region.y(),
+                part.width(),
+                part.height(),
+            )?;
+            Some(Piece {
+                source: to_physical(part, [(part, *output_scale)])?,
+                target: to_physical(offset, [(offset, scale)])?,
             })
-            .flatten()
+        })
+        .collect::<Vec<_>>();
+    Some(Area {
+        region: physical,
+        logical: region,
+        pieces: if pieces.len() > 1 { pieces } else { Vec::new() },
+        window: None,
+    })
+}
+
+/// The topmost of `windows`, listed bottom to top, under `pos`.
+fn window_at(windows: &[Region], pos: Pos) -> Option<usize> {
+    windows.iter().rposition(|window| {
+        (window.left()..window.right()).contains(&pos.x)
+            && (window.top()..window.bottom()).contains(&pos.y)
+    })
+}
+
+/// The area covered by an output in the physical pixels used for captures.
+pub(crate) fn output_physical_region(info: &OutputInfo) -> Option<Region> {
+    let region = output_region(info)?;
+    to_physical(region, [(region, output_scale(info))])
+}
+
+/// Map a logical `region` into physical pixels, using the scale of the output
+/// containing its origin. `outputs` are `(logical region, scale)` pairs.
+fn to_physical(region: Region, outputs: impl IntoIterator<Item = (Region, f64)>) -> Option<Region> {
+    let scale = outputs
+        .into_iter()
+        .find(|(output, _)| {
+            (output.left()..output.right()).contains(&region.x())
+                && (output.top()..output.bottom()).contains(&region.y())
+        })
+        .map_or(1., |(_, scale)| scale);
+    let scaled = |v: i32| (v as f64 * scale).round() as i32;
+    Region::from_xywh(
+        scaled(region.x()),
+        scaled(region.y()),
+        (region.width() as f64 * scale).round() as u32,
+        (region.height() as f64 * scale).round() as u32,
+    )
+}
+
+/// Fill a frame `width` wide around the outside of `rect`.
+///
+/// Filled rather than stroked, tiny-skia's thin anti-aliased strokes trip
+/// over debug assertions.
+fn fill_frame(
+    pixmap: &mut Pixmap,
+    rect: tiny_skia::Rect,
+    width: f32,
+    paint: &tiny_skia::Paint,
+    transform: tiny_skia::Transform,
+) {
+    use tiny_skia::Rect;
+    let (left, top, right, bottom) = (rect.left(), rect.top(), rect.right(), rect.bottom());
+    for edge in [
+        Rect::from_ltrb(left - width, top - width, right + width, top),
+        Rect::from_ltrb(left - width, bottom, right + width, bottom + width),
+        Rect::from_ltrb(left - width, top, left, bottom),
+        Rect::from_ltrb(right, top, right + width, bottom),
+    ]
+    .into_iter()
+    .flatten()
+    {
+        pixmap.fill_rect(edge, paint, transform, None);
+    }
+}
+
+/// Fill `outer` except for `hole`, as up to four bands around it, leaving
+/// the pixels inside the hole as they are.
+fn fill_around(
+    pixmap: &mut Pixmap,
+    outer: tiny_skia::Rect,
+    hole: Option<tiny_skia::Rect>,
+    paint: &tiny_skia::Paint,
+    transform: tiny_skia::Transform,
+) {
+    use tiny_skia::Rect;
+    let Some(hole) = hole.and_then(|hole| hole.intersect(&outer)) else {
+        pixmap.fill_rect(outer, paint, transform, None);
+        return;
+    };
+    let (left, top, right, bottom) = (outer.left(), outer.top(), outer.right(), outer.bottom());
+    for band in [
+        Rect::from_ltrb(left, top, right, hole.top()),
+        Rect::from_ltrb(left, hole.bottom(), right, bottom),
+        Rect::from_ltrb(left, hole.top(), hole.left(), hole.bottom()),
+        Rect::from_ltrb(hole.right(), hole.top(), right, hole.bottom()),
+    ]
+    .into_iter()
+    .flatten()
+    {
+        pixmap.fill_rect(band, paint, transform, None);
+    }
+}
+
+/// The buffers are bgra, swap red and blue so `color` shows as configured.
+fn to_bgra(color: Color) -> Color {
+    Color::from_rgba(color.blue(), color.green(), color.red(), color.alpha())
+        .expect("color channels are in range")
+}
+
+/// The user dismissed the overlay without selecting anything.
+#[derive(Debug)]
+pub struct Cancelled;
+impl fmt::Display for Cancelled {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "selection cancelled by user")
+    }
+}
+impl std::error::Error for Cancelled {}
+
+/// Nothing was selected before `SelectionConfig::timeout` ran out.
+#[derive(Debug)]
+pub struct TimedOut(pub Duration);
+impl fmt::Display for TimedOut {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "nothing selected within {}s", self.0.as_secs())
+    }
+}
+impl std::error::Error for TimedOut {}
+
+/// Options controlling how the overlay behaves.
+#[derive(Debug, Clone)]
+pub struct SelectionConfig {
+    /// click the two opposite corners instead of press-drag-release
+    pub two_click: bool,
+    /// fill of the overlay outside the selection
+    pub dim_color: Color,
+    /// guide lines following the pointer until a selection starts
+    pub guide_color: Color,
+    /// outline drawn just outside the selection
+    pub border_color: Color,
+    /// in logical pixels, 0 for no outline
+    pub border_width: u32,
+    /// frozen screen content to show instead of the live desktop
+    pub background: Option<Background>,
+    /// redraw at most this many times per second, the refresh rate by default
+    pub fps: Option<u32>,
+    /// show the background magnified next to the pointer
+    pub magnifier: bool,
+    /// keep the selection at this width to height ratio
+    pub aspect: Option<Aspect>,
+    /// give up when nothing is selected for this long
+    pub timeout: Option<Duration>,
+    /// how the overlay asks for keyboard focus
+    pub keyboard: OverlayKeyboard,
+    /// a click picks the pixel under the pointer instead of selecting
+    pub pick: bool,
+    /// a click picks the window under the pointer instead of selecting, or
+    /// the output when there is none, among these logical regions listed
+    /// bottom to top. The one under the pointer is highlighted
+    pub windows: Option<Vec<Region>>,
+    /// a right click cancels like Escape does, otherwise it is ignored
+    pub right_click_cancels: bool,
+}
+
+/// How the overlay asks for keyboard focus.
+#[derive(ValueEnum, Debug, Default, Clone, Copy, PartialEq, Eq)]
+pub enum OverlayKeyboard {
+    /// focus like a regular window
+    #[default]
+    OnDemand,
+    /// take all keyboard input while it is shown
+    Exclusive,
+    /// leave the keyboard alone, a right click still cancels
+    None,
+}
+impl OverlayKeyboard {
+    fn interactivity(self) -> KeyboardInteractivity {
+        match self {
+            Self::OnDemand => KeyboardInteractivity::OnDemand,
+            Self::Exclusive => KeyboardInteractivity::Exclusive,
+            Self::None => KeyboardInteractivity::None,
+        }
+    }
+}
+
+/// A capture of the whole workspace shown under the overlay.
+#[derive(Debug, Clone)]
+pub struct Background {
+    pub image: RgbaImage,
+    /// image pixels per logical pixel
+    pub scale: f64,
+}
+impl Background {
+    /// The part of the image covering the logical `region`, resampled to
+    /// `buffer_scale` pixels per logical pixel.
+    fn pixmap(&self, region: Region, buffer_scale: i32) -> Option<Pixmap> {
+        let buffer_scale = buffer_scale as u32;
+        let mut pixmap = Pixmap::new(
+            region.width() * buffer_scale,
+            region.height() * buffer_scale,
+        )?;
+        let width = pixmap.width() as usize;
+        for (i, pixel) in pixmap.pixels_mut().iter_mut().enumerate() {
+            let x = region.x() as f64 + (i % width) as f64 / buffer_scale as f64;
+            let y = region.y() as f64 + (i / width) as f64 / buffer_scale as f64;
+            let source = |v: f64| (v * self.scale).floor() as u32;
+            if let Some(image::Rgba([r, g, b, a])) =
+                self.image.get_pixel_checked(source(x), source(y))
+            {
+                *pixel = ColorU8::from_rgba(*b, *g, *r, *a).premultiply();
+            }
+        }
+        Some(pixmap)
+    }
+}
+impl Default for SelectionConfig {
+    fn default() -> Self {
+        Self {
+            two_click: false,
+            dim_color: Color::from_rgba8(0x64, 0x64, 0x64, 0x80),
+            guide_color: Color::from_rgba8(0xff, 0xff, 0xff, 0xa0),
+            border_color: Color::from_rgba8(0xff, 0xff, 0xff, 0xff),
+            border_width: 1,
+            background: None,
+            fps: None,
+            magnifier: false,
+            aspect: None,
+            timeout: None,
+            keyboard: OverlayKeyboard::OnDemand,
+            pick: false,
+            windows: None,
+            right_click_cancels: true,
+        }
+    }
+}
+
+/// The input devices of one seat.
+#[derive(Default)]
+struct SeatInput {
+    keyboard: Option<WlKeyboard>,
+    pointer: Option<ThemedPointer>,
+}
+impl SeatInput {
+    fn release(self) {
+        if let Some(keyboard) = self.keyboard {
+            keyboard.release();
+        }
+        if let Some(pointer) = self.pointer {
+            pointer.pointer().release();
+        }
     }
 }
 
 struct LayerState {
     registry_state: RegistryState,
     compositor_state: CompositorState,
+    layer_shell: LayerShell,
     shm: Shm,
     output_state: OutputState,
     seat_state: SeatState,
 
     pool: SlotPool,
     layer: Vec<LayerContext>,
-    keyboard: Option<WlKeyboard>,
-    // pointer: Option<WlPointer>,
-    pointer: Option<ThemedPointer>,
+    seats: HashMap<WlSeat, SeatInput>,
+    modifiers: Modifiers,
 
+    config: SelectionConfig,
     exit: bool,
-    pos_pressed: Option<Pos>,
+    cancelled: bool,
+    /// draw nothing, for --watch to capture what is under the overlay
+    hidden: bool,
+    /// dim around the selection, toggled with `d` to see dark content
+    dim_enabled: bool,
+    /// where the left button went down, and on which pointer
+    pos_pressed: Option<(WlPointer, Pos)>,
+    /// the corner or edge being dragged to resize the selection
+    handle: Option<Handle>,
+    /// coordinates being typed, after pressing `c`
+    entry: Option<Entry>,
+    /// the pixel clicked, when picking one
+    picked: Option<Pos>,
+    /// the index of the window under the pointer, when picking one
+    window: Option<usize>,
     pos_current: Pos, // current pointer postion
     selection: Selection,
-    last_draw: Instant,
 }
 impl LayerState {
-    pub fn draw(&mut self, conn: &Connection, qh: &QueueHandle<Self>, surface: &WlSurface) {
-        self.last_draw = Instant::now();
-        self.pointer.as_mut().map(|p| {
-            let _ = p.set_cursor(conn, CursorIcon::Crosshair);
-        });
-        self.layer
+    /// Put a layer surface on `output`, unless it has one already or is
+    /// missing what it takes to draw on it.
+    fn add_output(&mut self, qh: &QueueHandle<Self>, output: &WlOutput) {
+        if self.layer.iter().any(|ctx| &ctx.output == output) {
+            return;
+        }
+        // e.g. an output being plugged in or turned off right now
+        let Some(info) = self.output_state.info(output) else {
+            warn!("skipping output {} without information", output.id());
+            return;
+        };
+        let name = info.name.clone();
+        let label = name.as_deref().unwrap_or("(unnamed)");
+        let Some(region) = output_region(&info) else {
+            warn!("skipping output {label} without a position and size");
+            return;
+        };
+        let Some(pixmap) = Pixmap::new(region.width(), region.height()) else {
+            warn!("skipping output {label}, too large to draw on");
+            return;
+        };
+        let (scale, buffer_scale) = (output_scale(&info), info.scale_factor);
+        let surface = self.compositor_state.create_surface(qh);
+        let layer = self.layer_shell.create_layer_surface(
+            qh,
+            surface,
+            Layer::Overlay,
+            name,
+            Some(output),
+        );
+        layer.set_anchor(Anchor::TOP | Anchor::BOTTOM | Anchor::LEFT | Anchor::RIGHT);
+        layer.set_size(region.width(), region.height());
+        layer.set_keyboard_interactivity(self.config.keyboard.interactivity());
+        layer.commit();
+        let mut ctx = LayerContext {
+            layer,
+            output: output.clone(),
+            region,
+            scale,
+            buffer_scale: 1,
+            pixmap,
+            buffer: None,
+            background: None,
+            dirty: true,
+            frame_pending: false,
+            frame_interval: self
+                .config
+                .fps
+                .map(|fps| Duration::from_secs(1) / fps.max(1)),
+            last_draw: None,
+        };
+        ctx.set_buffer_scale(buffer_scale, self.config.background.as_ref());
+        self.layer.push(ctx);
+    }
+
+    /// Take the layer surface off `output`, ending the selection when no
+    /// output is left to select on.
+    fn remove_output(&mut self, output: &WlOutput) {
+        // dropping the surface destroys it
+        self.layer.retain(|ctx| &ctx.output != output);
+        if self.layer.is_empty() && !self.exit {
+            warn!("no output left to select on");
+            self.cancelled = true;
+            self.exit = true;
+        }
+    }
+
+    /// Text and position of the label showing the size of the selection, kept
+    /// on the output under the pointer.
+    ///
+    /// While coordinates are typed, shows them instead next to the pointer.
+    fn label(&self) -> Option<(String, Region)> {
+        if let Some(entry) = &self.entry {
+            let pointer = self.pos_current;
+            let bounds = self.output_at(pointer)?;
+            // the underscore tells typing is on
+            let text = format!("{}_", entry.text());
+            let anchor = Region::from_xywh(pointer.x, pointer.y, 1, 1)?;
+            let rect = label::place(anchor, bounds, label::size(&text))?;
+            return Some((text, rect));
+        }
+        let region = self.selection.to_region()?;
+        let pointer = self.selection.to()?;
+        let outputs = self.layer.iter().map(|ctx| (ctx.region, ctx.scale));
+        let physical = to_physical(region, outputs)?;
+        let bounds = self.output_at(pointer)?;
+        let text = format!("{}×{}", physical.width(), physical.height());
+        let rect = label::place(region, bounds, label::size(&text))?;
+        Some((text, rect))
+    }
+
+    /// The region of the output `pos` is on.
+    fn output_at(&self, pos: Pos) -> Option<Region> {
+        self.layer.iter().map(|ctx| ctx.region).find(|output| {
+            (output.left()..output.right()).contains(&pos.x)
+                && (output.top()..output.bottom()).contains(&pos.y)
+        })
+    }
+
+    /// Handle a key pressed while typing coordinates, Enter selects them.
+    fn type_coordinates(&mut self, event: &KeyEvent) {
+        let Some(entry) = self.entry.as_mut() else {
+            return;
+        };
+        match event.keysym {
+            Keysym::Escape => self.entry = None,
+            Keysym::BackSpace => entry.pop(),
+            Keysym::Return | Keysym::KP_Enter => match entry.region() {
+                Some(region) => {
+                    let from = Pos {
+                        x: region.left(),
+                        y: region.top(),
+                    };
+                    let to = Pos {
+                        x: region.right(),
+                        y: region.bottom(),
+                    };
+                    self.selection.select(from, to);
+                    self.entry = None;
+                }
+                None => debug!("{:?} is not x,y,w,h yet", entry.text()),
+            },
+            _ => event
+                .utf8
+                .iter()
+                .flat_map(|text| text.chars())
+                .for_each(|c| entry.push(c)),
+        }
+    }
+
+    /// Drop a press of `pointer` that will never be released.
+    fn forget_pointer(&mut self, pointer: Option<&ThemedPointer>) {
+        let Some(pointer) = pointer.map(ThemedPointer::pointer) else {
+            return;
+        };
+        if self.pos_pressed.as_ref().is_some_and(|(by, _)| by == pointer) {
+            self.pos_pressed = None;
+        }
+    }
+
+    /// Mark every surface as changed, drawing right away those which are not
+    /// waiting for a frame callback. The rest are drawn when it arrives.
+    pub fn request_redraw(&mut self, conn: &Connection, qh: &QueueHandle<Self>) {
+        let idle = self
+            .layer
+            .iter_mut()
+            .filter_map(|ctx| {
+                ctx.dirty = true;
+                (!ctx.frame_pending).then(|| ctx.layer.wl_surface().clone())
+            })
+            .collect::<Vec<_>>();
+        for surface in idle {
+            self.draw_when_due(conn, qh, &surface);
+        }
+    }
+
+    /// Draw `surface` now, or wait for another frame callback when it was
+    /// drawn too recently for `--fps`.
+    fn draw_when_due(&mut self, conn: &Connection, qh: &QueueHandle<Self>, surface: &WlSurface) {
+        let Some(ctx) = self
+            .layer
+            .iter_mut()
+            .find(|ctx| ctx.layer.wl_surface().id().eq(&surface.id()))
+        else {
+            return;
+        };
+        let due = ctx
+            .frame_interval
+            .zip(ctx.last_draw)
+            .is_none_or(|(interval, last)| frame_due(last.elapsed(), interval));
+        if due {
+            self.draw(conn, qh, surface);
+        } else {
+            surface.frame(qh, surface.clone());
+            ctx.frame_pending = true;
+            surface.commit();
+        }
+    }
+
+    /// Draw `surface` when its content changed since it was last drawn.
+    pub fn draw(&mut self, _conn: &Connection, qh: &QueueHandle<Self>, surface: &WlSurface) {
+        let label = self.label();
+        if let Some(ctx) = self
+            .layer
             .iter_mut()
             .find(|layer| layer.layer.wl_surface().id().eq(&surface.id()))
-            .map(|ctx| {
-                let width = ctx.region.width();
-                let height = ctx.region.height();
-                let (buffer, canvas) = self
-                    .pool
-                    .create_buffer(
-                        width as i32,
-                        height as i32,
-                        width as i32 * 4,
-                        wl_shm::Format::Argb8888,
-                    )
-                    .expect("create buffer");
-
-                ctx.pixmap.fill(Color::from_rgba8(0x64, 0x64, 0x64, 0x80)); // bgra
-                if self.selection.has_value() {
-                    use tiny_skia::*;
-                    let paint = {
-                        let mut paint = Paint::default();
-                        paint.set_color_rgba8(0, 0, 0, 0x00);
-                        paint.blend_mode = BlendMode::Source;
-                        paint
-                    };
-                    let from = self.selection.from().unwrap();
-                    let to = self.selection.to().unwrap();
-                    let rect = Rect::from_points(&[
-                        Point {
-                            x: from.x as f32,
-                            y: from.y as f32,
-                        },
-                        Point {
-                            x: to.x as f32,
-                            y: to.y as f32,
-                        },
-                    ])
-                    .unwrap();
-                    if rect.height() > 0. && rect.width() > 0. {
-                        ctx.pixmap.fill_rect(
-                            rect,
-                            &paint,
-                            Transform::from_translate(
-                                -ctx.region.x() as f32,
-                                -ctx.region.y() as f32,
-                            ),
-                            None,
-                        );
+            .filter(|ctx| ctx.dirty)
+        {
+            let width = ctx.pixmap.width();
+            let height = ctx.pixmap.height();
+            // draw in global logical coordinates
+            let scale = ctx.buffer_scale as f32;
+            let transform = tiny_skia::Transform::from_scale(scale, scale)
+                .pre_translate(-ctx.region.x() as f32, -ctx.region.y() as f32);
+            // reuse the buffer of the previous frame, unless the compositor
+            // still holds it or the size changed
+            let reusable = ctx.buffer.as_ref().is_some_and(|buffer| {
+                buffer.height() == height as i32
+                    && buffer.stride() == width as i32 * 4
+                    && buffer.canvas(&mut self.pool).is_some()
+            });
+            if !reusable {
+                match self.pool.create_buffer(
+                    width as i32,
+                    height as i32,
+                    width as i32 * 4,
+                    wl_shm::Format::Argb8888,
+                ) {
+                    Ok((buffer, _)) => ctx.buffer = Some(buffer),
+                    Err(err) => {
+                        // keep the overlay alive and try again on the next frame
+                        error!("failed to create buffer: {err}");
+                        surface.frame(qh, surface.clone());
+                        surface.commit();
+                        return;
                     }
                 }
+            }
 
-                canvas.copy_from_slice(ctx.pixmap.data());
+            match &ctx.background {
+                Some(background) => ctx.pixmap.data_mut().copy_from_slice(background.data()),
+                None => ctx.pixmap.fill(Color::TRANSPARENT),
+            }
+            let selected = self
+                .selection
+                .from()
+                .zip(self.selection.to())
+                .and_then(|(from, to)| {
+                    tiny_skia::Rect::from_points(&[
+                        tiny_skia::Point::from_xy(from.x as f32, from.y as f32),
+                        tiny_skia::Point::from_xy(to.x as f32, to.y as f32),
+                    ])
+                })
+                .filter(|rect| rect.width() > 0. && rect.height() > 0.);
+            // dim around the selection, what is under it shows unchanged
+            if self.dim_enabled {
+                let mut paint = tiny_skia::Paint {
+                    anti_alias: false,
+                    ..Default::default()
+                };
+                paint.set_color(to_bgra(self.config.dim_color));
+                fill_around(&mut ctx.pixmap, ctx.region.to_rect(), selected, &paint, transform);
+            }
+            if !self.selection.has_value() {
+                // guides through the pointer, across the whole output
+                let mut paint = tiny_skia::Paint::default();
+                paint.set_color(to_bgra(self.config.guide_color));
+                let (x, y) = (self.pos_current.x as f32, self.pos_current.y as f32);
+                let region = ctx.region.to_rect();
+                for line in [
+                    tiny_skia::Rect::from_xywh(x, region.y(), 1., region.height()),
+                    tiny_skia::Rect::from_xywh(region.x(), y, region.width(), 1.),
+                ]
+                .into_iter()
+                .flatten()
+                {
+                    ctx.pixmap.fill_rect(line, &paint, transform, None);
+                }
+            }
+            // just outside, the inside stays clear
+            if let Some(rect) = selected.filter(|_| self.config.border_width > 0) {
+                let mut paint = tiny_skia::Paint::default();
+                paint.set_color(to_bgra(self.config.border_color));
+                let width = self.config.border_width as f32;
+                fill_frame(&mut ctx.pixmap, rect, width, &paint, transform);
+            }
+            if let Some((text, rect)) = &label {
+                label::draw(&mut ctx.pixmap, text, *rect, transform);
+            }
+            let pointer = (self.pos_current.x, self.pos_current.y);
+            let under_pointer = (ctx.region.left()..ctx.region.right()).contains(&pointer.0)
+                && (ctx.region.top()..ctx.region.bottom()).contains(&pointer.1);
+            let loupe_background = ctx
+                .background
+                .as_ref()
+                .filter(|_| self.config.magnifier && under_pointer);
+            if let Some(background) = loupe_background {
+                if let Some(rect) = loupe::place(pointer, ctx.region) {
+                    loupe::draw(
+                        &mut ctx.pixmap,
+                        background,
+                        (ctx.region.x(), ctx.region.y()),
+                        ctx.buffer_scale,
+                        pointer,
+                        rect,
+                        to_bgra(self.config.guide_color),
+                        transform,
+                    );
+                }
+            }
 
-                surface.damage_buffer(0, 0, width as i32, height as i32);
+            // cheaper than skipping each of the steps above, and only for a
+            // frame per capture
+            if self.hidden {
+                ctx.pixmap.fill(Color::TRANSPARENT);
+            }
 
-                buffer.attach_to(surface).expect("buffer attach");
+            let buffer = ctx.buffer.as_ref().expect("buffer was just checked");
+            let canvas = buffer
+                .canvas(&mut self.pool)
+                .expect("buffer is not held by the compositor");
+            canvas.copy_from_slice(ctx.pixmap.data());
 
-                // request redraw with current buffer and call frame callback
-                surface.frame(qh, surface.clone());
+            surface.damage_buffer(0, 0, width as i32, height as i32);
 
-                surface.commit();
-            });
+            buffer.attach_to(surface).expect("buffer attach");
+
+            // get notified when the compositor is ready for the next frame
+            surface.frame(qh, surface.clone());
+            ctx.dirty = false;
+            ctx.frame_pending = true;
+            ctx.last_draw = Some(Instant::now());
+
+            surface.commit();
+        }
     }
 }
 
@@ -219,11 +1105,26 @@ delegate_compositor!(LayerState);
 impl CompositorHandler for LayerState {
     fn scale_factor_changed(
         &mut self,
-        _conn: &Connection,
-        _qh: &QueueHandle<Self>,
-        _surface: &WlSurface,
-        _new_factor: i32,
+        conn: &Connection,
+        qh: &QueueHandle<Self>,
+        surface: &WlSurface,
+        new_factor: i32,
     ) {
+        let background = self.config.background.as_ref();
+        let Some(ctx) = self
+            .layer
+            .iter_mut()
+            .find(|ctx| ctx.layer.wl_surface().id().eq(&surface.id()))
+        else {
+            return;
+        };
+        if ctx.buffer_scale == new_factor {
+            return;
+        }
+        ctx.set_buffer_scale(new_factor, background);
+        if !ctx.frame_pending {
+            self.draw(conn, qh, surface);
+        }
     }
 
     fn transform_changed(
@@ -233,6 +1134,8 @@ impl CompositorHandler for LayerState {
         _surface: &WlSurface,
         _new_transform: Transform,
     ) {
+        // nothing to do, buffers are drawn upright in logical coordinates and
+        // the compositor rotates them along with the output
     }
 
     fn frame(
@@ -242,20 +1145,17 @@ impl CompositorHandler for LayerState {
         surface: &WlSurface,
         _time: u32,
     ) {
-        // frame callback
-        self.selection.update(self.pos_current);
-
-        {
-            let fps = 60;
-            let interval = 1000 / fps;
-            let now = Instant::now();
-            let elapsed_ms = now.duration_since(self.last_draw).as_millis();
-            if elapsed_ms < interval {
-                std::thread::sleep(std::time::Duration::from_millis(
-                    (interval - elapsed_ms) as u64,
-                ));
-            }
-            self.draw(conn, qh, surface);
+        // frame callback, only redraw when something changed meanwhile
+        let dirty = self
+            .layer
+            .iter_mut()
+            .find(|ctx| ctx.layer.wl_surface().id().eq(&surface.id()))
+            .map(|ctx| {
+                ctx.frame_pending = false;
+                ctx.dirty
+            });
+        if dirty == Some(true) {
+            self.draw_when_due(conn, qh, surface);
         }
     }
     
@@ -283,11 +1183,28 @@ impl OutputHandler for LayerState {
         &mut self.output_state
     }
 
-    fn new_output(&mut self, _conn: &Connection, _qh: &QueueHandle<Self>, _output: WlOutput) {}
+    fn new_output(&mut self, _conn: &Connection, qh: &QueueHandle<Self>, output: WlOutput) {
+        self.add_output(qh, &output);
+    }
 
-    fn update_output(&mut self, _conn: &Connection, _qh: &QueueHandle<Self>, _output: WlOutput) {}
+    fn update_output(&mut self, _conn: &Connection, qh: &QueueHandle<Self>, output: WlOutput) {
+        let Some(ctx) = self.layer.iter().find(|ctx| ctx.output == output) else {
+            return;
+        };
+        let region = self.output_state.info(&output).as_ref().and_then(output_region);
+        if region == Some(ctx.region) {
+            return;
+        }
+        // moved or resized, a new surface is simpler than fixing up the old one
+        self.layer.retain(|ctx| ctx.output != output);
+        self.add_output(qh, &output);
+        if self.layer.is_empty() {
+            self.remove_output(&output);
+        }
+    }
 
-    fn output_destroyed(&mut self, _conn: &Connection, _qh: &QueueHandle<Self>, _output: WlOutput) {
+    fn output_destroyed(&mut self, _conn: &Connection, _qh: &QueueHandle<Self>, output: WlOutput) {
+        self.remove_output(&output);
     }
 }
 
@@ -311,7 +1228,15 @@ impl LayerShellHandler for LayerState {
         _configure: LayerSurfaceConfigure,
         _serial: u32,
     ) {
-        // start first draw here
+        // start first draw here, a later configure is rare and is acked by
+        // the commit of a new draw
+        if let Some(ctx) = self
+            .layer
+            .iter_mut()
+            .find(|ctx| ctx.layer.wl_surface().id().eq(&layer.wl_surface().id()))
+        {
+            ctx.dirty = true;
+        }
         self.draw(conn, qh, layer.wl_surface());
     }
 }
@@ -321,7 +1246,9 @@ impl SeatHandler for LayerState {
         &mut self.seat_state
     }
 
-    fn new_seat(&mut self, _conn: &Connection, _qh: &QueueHandle<Self>, _seat: WlSeat) {}
+    fn new_seat(&mut self, _conn: &Connection, _qh: &QueueHandle<Self>, seat: WlSeat) {
+        self.seats.entry(seat).or_default();
+    }
 
     fn new_capability(
         &mut self,
@@ -330,20 +1257,25 @@ impl SeatHandler for LayerState {
         seat: WlSeat,
         capability: Capability,
     ) {
-        if capability == Capability::Keyboard && self.keyboard.is_none() {
-            let keyboard = self
-                .seat_state
-                .get_keyboard(qh, &seat, None)
-                .expect("Failed to create keyboard");
-            self.keyboard = Some(keyboard);
-        }
-        if capability == Capability::Pointer && self.pointer.is_none() {
+        let input = self.seats.entry(seat.clone()).or_default();
+        if capability == Capability::Keyboard && input.keyboard.is_none() {
+            match self.seat_state.get_keyboard(qh, &seat, None) {
+                Ok(keyboard) => input.keyboard = Some(keyboard),
+                Err(err) => warn!("failed to get the keyboard of seat {}: {err}", seat.id()),
+            }
+        }
+        if capability == Capability::Pointer && input.pointer.is_none() {
             let surface = self.compositor_state.create_surface(qh);
-            let pointer = self
-                .seat_state
-                .get_pointer_with_theme(qh, &seat, self.shm.wl_shm(), surface, ThemeSpec::default())
-                .expect("Failed to create pointer");
-            self.pointer = Some(pointer);
+            match self.seat_state.get_pointer_with_theme(
+                qh,
+                &seat,
+                self.shm.wl_shm(),
+                surface,
+                ThemeSpec::default(),
+            ) {
+                Ok(pointer) => input.pointer = Some(pointer),
+                Err(err) => warn!("failed to get the pointer of seat {}: {err}", seat.id()),
+            }
         }
     }
 
@@ -351,21 +1283,34 @@ impl SeatHandler for LayerState {
         &mut self,
         _conn: &Connection,
         _qh: &QueueHandle<Self>,
-        _seat: WlSeat,
+        seat: WlSeat,
         capability: Capability,
     ) {
-        if capability == Capability::Keyboard && self.keyboard.is_some() {
-            println!("Unset keyboard capability");
-            self.keyboard.take().unwrap().release();
-        }
+        let Some(input) = self.seats.get_mut(&seat) else {
+            return;
+        };
+        let removed = match capability {
+            Capability::Keyboard => SeatInput {
+                keyboard: input.keyboard.take(),
+                pointer: None,
+            },
+            Capability::Pointer => SeatInput {
+                keyboard: None,
+                pointer: input.pointer.take(),
+            },
+            _ => return,
+        };
+        debug!("seat {} lost its {capability}", seat.id());
+        self.forget_pointer(removed.pointer.as_ref());
+        removed.release();
+    }
 
-        if capability == Capability::Pointer && self.pointer.is_some() {
-            println!("Unset pointer capability");
-            self.pointer.take().unwrap().pointer().release();
+    fn remove_seat(&mut self, _conn: &Connection, _qh: &QueueHandle<Self>, seat: WlSeat) {
+        if let Some(input) = self.seats.remove(&seat) {
+            self.forget_pointer(input.pointer.as_ref());
+            input.release();
         }
     }
-
-    fn remove_seat(&mut self, _conn: &Connection, _qh: &QueueHandle<Self>, _seat: WlSeat) {}
 }
 
 delegate_keyboard!(LayerState);
@@ -394,14 +1339,59 @@ impl KeyboardHandler for LayerState {
 
     fn press_key(
         &mut self,
-        _conn: &Connection,
-        _qh: &QueueHandle<Self>,
+        conn: &Connection,
+        qh: &QueueHandle<Self>,
         _keyboard: &WlKeyboard,
         _serial: u32,
         event: KeyEvent,
     ) {
-        if event.keysym == Keysym::Escape {
-            self.exit = true;
+        if self.entry.is_some() {
+            self.type_coordinates(&event);
+            self.request_redraw(conn, qh);
+            return;
+        }
+        let step = if self.modifiers.shift { 10 } else { 1 };
+        match event.keysym {
+            Keysym::Escape if self.config.two_click && self.selection.on => {
+                // drop the first corner and wait for a new one
+                self.selection.reset();
+                self.request_redraw(conn, qh);
+            }
+            Keysym::Escape => {
+                self.cancelled = true;
+                self.exit = true;
+            }
+            Keysym::Return | Keysym::KP_Enter if self.selection.usable_region().is_none() => {
+                // nothing to capture yet, keep selecting
+                debug!("selection is smaller than {MIN_SELECTION_SIZE}x{MIN_SELECTION_SIZE}, ignoring Enter");
+            }
+            Keysym::Return | Keysym::KP_Enter => {
+                if let Some(to) = self.selection.to() {
+                    self.selection.end(to);
+                }
+                self.pos_pressed = None;
+                self.exit = true;
+            }
+            // move the moving corner of the selection
+            Keysym::Left | Keysym::Right | Keysym::Up | Keysym::Down => {
+                let (dx, dy) = match event.keysym {
+                    Keysym::Left => (-step, 0),
+                    Keysym::Right => (step, 0),
+                    Keysym::Up => (0, -step),
+                    _ => (0, step),
+                };
+                self.selection.nudge(dx, dy);
+                self.request_redraw(conn, qh);
+            }
+            Keysym::c => {
+                self.entry = Some(Entry::default());
+                self.request_redraw(conn, qh);
+            }
+            Keysym::d => {
+                self.dim_enabled = !self.dim_enabled;
+                self.request_redraw(conn, qh);
+            }
+            _ => {}
         }
     }
 
@@ -421,64 +1411,182 @@ impl KeyboardHandler for LayerState {
         _qh: &QueueHandle<Self>,
         _keyboard: &wayland_client::protocol::wl_keyboard::WlKeyboard,
         _serial: u32,
-        _modifiers: smithay_client_toolkit::seat::keyboard::Modifiers,
+        modifiers: Modifiers,
         _layout: u32,
     ) {
+        self.modifiers = modifiers;
     }
 }
 delegate_pointer!(LayerState);
 impl PointerHandler for LayerState {
     fn pointer_frame(
         &mut self,
-        _conn: &Connection,
-        _qh: &QueueHandle<Self>,
-        _pointer: &WlPointer,
+        conn: &Connection,
+        qh: &QueueHandle<Self>,
+        pointer: &WlPointer,
         events: &[PointerEvent],
     ) {
         use PointerEventKind::*;
+        let before = (self.selection.data, self.selection.on, self.pos_current);
         for event in events {
-            let region = self
+            // e.g. for a surface taken down with its output, or events racing
+            // a quick switch between outputs
+            let Some(region) = self
                 .layer
                 .iter()
                 .find(|layer| layer.layer.wl_surface().id().eq(&event.surface.id()))
                 .map(|ctx| ctx.region)
-                .unwrap();
+            else {
+                trace!("ignoring {:?} on unknown surface {}", event.kind, event.surface.id());
+                continue;
+            };
             let pos = Pos {
                 x: event.position.0.floor() as i32 + region.x(),
                 y: event.position.1.floor() as i32 + region.y(),
             };
             self.pos_current = pos;
 
-            if let Some(pressed_pos) = self.pos_pressed {
-                if !pos.eq(&pressed_pos) {
+            if let Some(windows) = &self.config.windows {
+                self.window = window_at(windows, pos);
+                let highlighted = self.window.map_or(region, |i| windows[i]);
+                self.selection.select(
+                    Pos {
+                        x: highlighted.left(),
+                        y: highlighted.top(),
+                    },
+                    Pos {
+                        x: highlighted.right(),
+                        y: highlighted.bottom(),
+                    },
+                );
+            }
+
+            // only the pointer holding the button down drags
+            let pressed_here = self.pos_pressed.as_ref().filter(|(by, _)| by == pointer);
+            if let Some(&(_, pressed_pos)) = pressed_here {
+                if !pos.eq(&pressed_pos) && !self.selection.on {
                     self.selection.begin(pressed_pos);
                 }
             }
 
             match event.kind {
-                Enter { .. } => {}
+                Enter { .. } => {
+                    let themed = self.seats.values().filter_map(|input| input.pointer.as_ref());
+                    if let Some(themed) = themed.into_iter().find(|p| p.pointer() == pointer) {
+                        let _ = themed.set_cursor(conn, CursorIcon::Crosshair);
+                    }
+                }
                 Leave { .. } => {}
-                Press { button, .. } => {
-                    event.position;
-                    if button & BTN_LEFT > 0 {
-                        self.pos_pressed = Some(pos);
+                Press { button, .. } if button == BTN_LEFT && self.config.pick => {
+                    self.picked = Some(pos);
+                    self.exit = true;
+                }
+                // the window, or output, highlighted above
+                Press { button, .. } if button == BTN_LEFT && self.config.windows.is_some() => {
+                    self.exit = true;
+                }
+                Press { button, .. } if button == BTN_LEFT => {
+                    if let Some(handle) = self.selection.handle_at(pos) {
+                        self.selection.hold(handle);
+                        self.handle = Some(handle);
+                    } else if self.selection.grab(pos, region) {
+                        // moving the whole selection, see `Selection::update`
+                    } else if !self.config.two_click {
+                        self.pos_pressed = Some((pointer.clone(), pos));
+                    } else if !self.selection.on {
+                        // first click: anchor the corner, rubber-band follows the pointer
+                        self.selection.begin(pos);
+                    } else {
+                        // second click: opposite corner, Enter confirms
+                        self.selection.end(pos);
                     }
                 }
-                Release { button, .. } => {
-                    event.position;
-                    if button & BTN_LEFT > 0 {
-                        self.pos_pressed = None;
+                Press { button, .. } if button == BTN_RIGHT && self.config.right_click_cancels => {
+                    self.cancelled = true;
+                    self.exit = true;
+                }
+                Release { button, .. } if button == BTN_LEFT && self.handle.is_some() => {
+                    self.handle = None;
+                }
+                Release { button, .. } if button == BTN_LEFT && self.selection.release() => {}
+                Release { button, .. } if button == BTN_LEFT && !self.config.two_click => {
+                    // keep the overlay up to refine or redo it, Enter confirms
+                    if self.pos_pressed.as_ref().is_some_and(|(by, _)| by != pointer) {
+                        // another seat is dragging
+                        continue;
+                    }
+                    let pressed = self.pos_pressed.take();
+                    if pressed.is_some_and(|(_, pressed)| is_click(pressed, pos)) {
+                        // a click selects the whole output
+                        self.selection.select(
+                            Pos {
+                                x: region.left(),
+                                y: region.top(),
+                            },
+                            Pos {
+                                x: region.right(),
+                                y: region.bottom(),
+                            },
+                        );
+                    } else {
                         self.selection.end(pos);
-                        self.exit = true;
                     }
                 }
                 _ => {}
             }
         }
+
+        match self.handle {
+            Some(handle) => self.selection.resize(handle, self.pos_current),
+            None => self.selection.update(self.pos_current),
+        }
+        // skip when nothing shown moved, e.g. on a press or a lone button frame
+        if before != (self.selection.data, self.selection.on, self.pos_current) {
+            self.request_redraw(conn, qh);
+        }
+    }
+}
+
+/// Show the overlay as `config` tells and wait for a selection.
+///
+/// Fails with `Cancelled` when the user cancels and `TimedOut` once
+/// `config.timeout` runs out.
+pub fn wait_for_selection_with(config: SelectionConfig) -> Result<Area> {
+    let mut selected = None;
+    run_overlay(config, false, &mut |area| {
+        selected = Some(area);
+        Ok(())
+    })?;
+    selected.ok_or(anyhow!("the overlay closed without a selection"))?
+}
+
+/// Keep the overlay up after each selection, handing it to `on_select` with
+/// the overlay hidden so it can be captured, until the user presses Escape.
+pub fn watch_selections(
+    config: SelectionConfig,
+    mut on_select: impl FnMut(Area) -> Result<()>,
+) -> Result<()> {
+    let result = run_overlay(config, true, &mut |area| match area {
+        Ok(area) => on_select(area),
+        // a stray click, not a reason to stop watching
+        Err(err) => {
+            warn!("{err:#}");
+            Ok(())
+        }
+    });
+    match result {
+        Err(err) if err.is::<Cancelled>() => Ok(()),
+        result => result,
     }
 }
 
-pub fn wait_for_selection() -> Result<Region> {
+/// Show the overlay and pass the selection made on it to `on_select`, then
+/// with `watch` hide it meanwhile and let the user select again.
+fn run_overlay(
+    config: SelectionConfig,
+    watch: bool,
+    on_select: &mut dyn FnMut(Result<Area>) -> Result<()>,
+) -> Result<()> {
     let conn = Connection::connect_to_env()?;
     let (globals, mut event_queue) = registry_queue_init::<LayerState>(&conn)?;
     let qh = event_queue.handle();
@@ -490,70 +1598,574 @@ pub fn wait_for_selection() -> Result<Region> {
     let layer_shell = LayerShell::bind(&globals, &qh)?;
     let shm = Shm::bind(&globals, &qh)?;
     let seat_state = SeatState::new(&globals, &qh);
-    let pool = SlotPool::new(1920 * 1080 * 4, &shm)?;
+    // replaced by one fitting the outputs below
+    let pool = SlotPool::new(4096, &shm)?;
 
+    let selection = Selection {
+        aspect: config.aspect,
+        ..Default::default()
+    };
     let mut layer_state = LayerState {
         registry_state,
         compositor_state,
+        layer_shell,
         shm,
         output_state,
         seat_state,
 
         pool,
         layer: Vec::new(),
-        keyboard: None,
-        pointer: None,
-        // themed_pointer: None,
+        seats: HashMap::new(),
+        modifiers: Default::default(),
+        // dimmed, the pixels look darker than what is picked
+        dim_enabled: !config.pick,
+        config,
         exit: false,
+        cancelled: false,
+        hidden: false,
         pos_pressed: None,
+        entry: None,
+        picked: None,
+        window: None,
+        handle: None,
         pos_current: Default::default(),
-        selection: Default::default(),
-        last_draw: Instant::now(),
+        selection,
     };
     // get output
     event_queue.roundtrip(&mut layer_state)?;
 
-    // init layer
-    layer_state.output_state.outputs().for_each(|output| {
-        let (name, region) = layer_state
-            .output_state
-            .info(&output)
-            .map(|info| {
-                let region = Region::from_xywh(
-                    info.logical_position.unwrap().0,
-                    info.logical_position.unwrap().1,
-                    info.logical_size.unwrap().0 as u32,
-                    info.logical_size.unwrap().1 as u32,
-                )
-                .unwrap();
-                (info.name, region)
-            })
-            .unwrap();
-        let surface = layer_state.compositor_state.create_surface(&qh);
-        let layer =
-            layer_shell.create_layer_surface(&qh, surface, Layer::Overlay, name, Some(&output));
-        layer.set_anchor(Anchor::TOP | Anchor::BOTTOM | Anchor::LEFT | Anchor::RIGHT);
-        layer.set_size(region.width(), region.height());
-        layer.set_keyboard_interactivity(KeyboardInteractivity::OnDemand);
-        layer.commit();
-        let pixmap = Pixmap::new(region.width(), region.height()).unwrap();
-        layer_state.layer.push(LayerContext {
-            layer,
-            region,
-            pixmap,
-        });
-    });
+    // init layer, outputs announced during the roundtrip have one already
+    let outputs = layer_state.output_state.outputs().collect::<Vec<_>>();
+    for output in outputs {
+        layer_state.add_output(&qh, &output);
+    }
+    if layer_state.layer.is_empty() {
+        return Err(anyhow!("no output with a known geometry to select on"));
+    }
+    let sizes = layer_state
+        .layer
+        .iter()
+        .map(|ctx| ctx.pixmap.width() as usize * ctx.pixmap.height() as usize * 4)
+        .collect::<Vec<_>>();
+    // a new pool each time, a failed resize leaves the old one unusable
+    layer_state.pool = fit_pool(&sizes, |size| SlotPool::new(size, &layer_state.shm))?;
     event_queue.roundtrip(&mut layer_state)?;
 
+    let timeout = layer_state.config.timeout;
+    let mut deadline = timeout.map(|timeout| Instant::now() + timeout);
     loop {
-        event_queue.blocking_dispatch(&mut layer_state)?;
-        if layer_state.exit {
-            break;
+        match deadline {
+            Some(deadline) => {
+                if !dispatch_until(&mut event_queue, &mut layer_state, deadline)? {
+                    // dropping the surfaces takes the overlay down
+                    return Err(TimedOut(timeout.unwrap_or_default()).into());
+                }
+            }
+            None => {
+                event_queue.blocking_dispatch(&mut layer_state)?;
+            }
+        }
+        if !layer_state.exit {
+            continue;
+        }
+        if layer_state.cancelled {
+            return Err(Cancelled.into());
+        }
+
+        let area = match layer_state.picked {
+            Some(pos) => Region::from_xywh(pos.x, pos.y, 1, 1)
+                .map(Area::single)
+                .ok_or(anyhow!("failed to pick a pixel")),
+            None => selected_area(&layer_state),
+        };
+        if !watch {
+            return on_select(area);
+        }
+        hide_overlay(&conn, &mut event_queue, &mut layer_state)?;
+        on_select(area)?;
+        // out of the way while the callback ran, back for the next selection
+        layer_state.hidden = false;
+        layer_state.exit = false;
+        layer_state.selection.reset();
+        layer_state.pos_pressed = None;
+        layer_state.handle = None;
+        layer_state.entry = None;
+        layer_state.request_redraw(&conn, &qh);
+        deadline = timeout.map(|timeout| Instant::now() + timeout);
+    }
+}
+
+/// Take the overlay off the screen, returning once the compositor presented
+/// its transparent frames.
+fn hide_overlay(
+    conn: &Connection,
+    event_queue: &mut EventQueue<LayerState>,
+    state: &mut LayerState,
+) -> Result<()> {
+    state.hidden = true;
+    state.request_redraw(conn, &event_queue.handle());
+    while state.layer.iter().any(|ctx| ctx.dirty || ctx.frame_pending) {
+        event_queue.blocking_dispatch(state)?;
+    }
+    Ok(())
+}
+
+/// The area selected on the overlay, in the pieces to capture it in.
+fn selected_area(layer_state: &LayerState) -> Result<Area> {
+    let region = layer_state.selection.usable_region().ok_or(anyhow!(
+        "selection is smaller than {MIN_SELECTION_SIZE}x{MIN_SELECTION_SIZE} pixels"
+    ))?;
+    let bounds = layer_state.layer.iter().map(|ctx| ctx.region).collect::<Vec<_>>();
+    let region = clamp_to(region, &bounds).ok_or(anyhow!("selection is outside of every output"))?;
+    // for --last, not worth failing over
+    if let Err(err) = state::save_last_region(region) {
+        warn!("failed to remember the selection: {err:#}");
+    }
+
+    // the capture is taken at native resolution
+    let outputs = layer_state
+        .layer
+        .iter()
+        .map(|ctx| (ctx.region, ctx.scale))
+        .collect::<Vec<_>>();
+    let area = split(region, &outputs).ok_or(anyhow!("failed to scale selection"))?;
+    Ok(Area {
+        window: layer_state.window,
+        ..area
+    })
+}
+
+/// Like `EventQueue::blocking_dispatch`, but sleeping no later than
+/// `deadline`. `false` once it has passed.
+fn dispatch_until<D>(queue: &mut EventQueue<D>, state: &mut D, deadline: Instant) -> Result<bool> {
+    if Instant::now() >= deadline {
+        return Ok(false);
+    }
+    if queue.dispatch_pending(state)? > 0 {
+        return Ok(true);
+    }
+    queue.flush()?;
+    let Some(guard) = queue.prepare_read() else {
+        // events came in meanwhile
+        queue.dispatch_pending(state)?;
+        return Ok(true);
+    };
+
+    let left = deadline.saturating_duration_since(Instant::now());
+    // rounded up, waking just before the deadline would only poll again
+    let millis = left.as_micros().div_ceil(1000).min(libc::c_int::MAX as u128);
+    let mut fd = libc::pollfd {
+        fd: guard.connection_fd().as_raw_fd(),
+        events: libc::POLLIN,
+        revents: 0,
+    };
+    match unsafe { libc::poll(&mut fd, 1, millis as libc::c_int) } {
+        0 => return Ok(Instant::now() < deadline),
+        -1 => {
+            let err = io::Error::last_os_error();
+            if err.kind() == io::ErrorKind::Interrupted {
+                return Ok(true);
+            }
+            return Err(err.into());
+        }
+        _ => {}
+    }
+    match guard.read() {
+        Ok(_) => {}
+        Err(WaylandError::Io(err)) if err.kind() == io::ErrorKind::WouldBlock => {}
+        Err(err) => return Err(DispatchError::from(err).into()),
+    }
+    queue.dispatch_pending(state)?;
+    Ok(true)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn region(x: i32, y: i32, w: u32, h: u32) -> Region {
+        Region::from_xywh(x, y, w, h).unwrap()
+    }
+
+    #[test]
+    fn test_frame_due() {
+        let interval = Duration::from_millis(16);
+        assert!(frame_due(Duration::from_millis(16), interval));
+        assert!(frame_due(Duration::from_millis(15), interval));
+        assert!(!frame_due(Duration::from_millis(7), interval));
+    }
+
+    #[test]
+    fn test_fit_pool() {
+        // room for 10000 bytes
+        let allocate = |size: usize| if size <= 10_000 { Ok(size) } else { Err("no room") };
+        assert_eq!(fit_pool(&[4000, 3000], allocate).unwrap(), 7000);
+        assert_eq!(fit_pool(&[4000, 4000, 4000], allocate).unwrap(), 6000);
+        // halved no further than a single buffer
+        assert_eq!(fit_pool(&[9000, 8000, 7000], allocate).unwrap(), 9000);
+        assert!(fit_pool(&[12_000, 100], allocate).is_err());
+
+        let mut tried = Vec::new();
+        let _ = fit_pool(&[12_000, 8000], |size| {
+            tried.push(size);
+            allocate(size)
+        });
+        assert_eq!(tried, [20_000, 12_000]);
+    }
+
+    #[test]
+    fn test_is_click() {
+        let at = |x, y| Pos { x, y };
+        assert!(is_click(at(10, 10), at(10, 10)));
+        assert!(is_click(at(10, 10), at(13, 7)));
+        assert!(!is_click(at(10, 10), at(14, 10)));
+        assert!(!is_click(at(10, 10), at(10, 50)));
+    }
+
+    #[test]
+    fn test_selection_move() {
+        let at = |x, y| Pos { x, y };
+        let output = region(0, 0, 100, 100);
+        let mut selection = Selection::default();
+        assert!(!selection.grab(at(10, 10), output));
+
+        selection.begin(at(10, 10));
+        selection.update(at(20, 30));
+        // still being drawn
+        assert!(!selection.grab(at(15, 15), output));
+        selection.end(at(20, 30));
+        assert!(!selection.grab(at(25, 15), output));
+
+        assert!(selection.grab(at(15, 15), output));
+        selection.update(at(18, 14));
+        selection.update(at(20, 13));
+        assert_eq!(selection.to_region(), Some(region(15, 8, 10, 20)));
+        assert!(selection.release());
+        assert!(!selection.release());
+        // no longer follows the pointer
+        selection.update(at(50, 50));
+        assert_eq!(selection.to_region(), Some(region(15, 8, 10, 20)));
+    }
+
+    #[test]
+    fn test_selection_grab_whole_output() {
+        let at = |x, y| Pos { x, y };
+        let mut selection = Selection::default();
+        // as selected by a click
+        selection.select(at(0, 0), at(100, 100));
+        assert!(!selection.grab(at(50, 50), region(0, 0, 100, 100)));
+        // the same region is only part of a larger output
+        assert!(selection.grab(at(50, 50), region(0, 0, 200, 100)));
+    }
+
+    #[test]
+    fn test_selection_handles() {
+        let at = |x, y| Pos { x, y };
+        let handle = |x, y| Some(Handle { x, y });
+        let mut selection = Selection::default();
+        selection.select(at(100, 100), at(200, 150));
+
+        assert_eq!(selection.handle_at(at(98, 103)), handle(Some(false), Some(false)));
+        assert_eq!(selection.handle_at(at(204, 152)), handle(Some(true), Some(true)));
+        assert_eq!(selection.handle_at(at(150, 147)), handle(None, Some(true)));
+        assert_eq!(selection.handle_at(at(101, 120)), handle(Some(false), None));
+        // inside, where it moves instead, and out of reach
+        assert_eq!(selection.handle_at(at(150, 120)), None);
+        assert_eq!(selection.handle_at(at(150, 160)), None);
+
+        // the left edge only moves sideways
+        let left = Handle { x: Some(false), y: None };
+        selection.hold(left);
+        selection.resize(left, at(90, 10));
+        assert_eq!(selection.to_region(), Some(region(90, 100, 110, 50)));
+
+        // the top-left corner dragged past the bottom-right one
+        let corner = Handle { x: Some(false), y: Some(false) };
+        selection.hold(corner);
+        selection.resize(corner, at(210, 160));
+        assert_eq!(selection.to_region(), Some(region(200, 150, 10, 10)));
+    }
+
+    #[test]
+    fn test_to_region_extremes() {
+        let at = |x, y| Pos { x, y };
+        let mut selection = Selection::default();
+        selection.select(at(i32::MIN, 0), at(i32::MAX, 10));
+        // wider than a region can be
+        assert_eq!(selection.to_region(), None);
+        selection.select(at(i32::MAX, i32::MIN), at(i32::MIN, i32::MAX));
+        assert_eq!(selection.to_region(), None);
+
+        selection.select(at(i32::MIN, -10), at(-1, 10));
+        assert_eq!(
+            selection.to_region(),
+            Region::from_xywh(i32::MIN, -10, i32::MAX as u32, 20)
+        );
+        selection.select(at(i32::MAX, i32::MAX), at(i32::MAX - 20, i32::MAX - 10));
+        assert_eq!(
+            selection.to_region(),
+            Region::from_xywh(i32::MAX - 20, i32::MAX - 10, 20, 10)
+        );
+    }
+
+    #[test]
+    fn test_selection_nudge() {
+        let mut selection = Selection::default();
+        selection.nudge(1, 1);
+        assert_eq!(selection.to_region(), None);
+
+        selection.begin(Pos { x: 10, y: 10 });
+        selection.end(Pos { x: 20, y: 30 });
+        selection.nudge(-10, 0);
+        selection.nudge(0, 1);
+        // no width left
+        assert_eq!(selection.to_region(), None);
+        selection.nudge(5, 0);
+        assert_eq!(selection.to_region(), Some(region(10, 10, 5, 21)));
+    }
+
+    #[test]
+    fn test_aspect() {
+        let square = Aspect {
+            width: 1,
+            height: 1,
+        };
+        let wide = Aspect {
+            width: 16,
+            height: 9,
+        };
+        let at = |x, y| Pos { x, y };
+        let from = at(100, 100);
+        // the longer side wins
+        assert_eq!(square.constrain(from, at(150, 120)), at(150, 150));
+        assert_eq!(square.constrain(from, at(110, 160)), at(160, 160));
+        // up and to the left of the anchor
+        assert_eq!(square.constrain(from, at(50, 90)), at(50, 50));
+        assert_eq!(square.constrain(from, at(90, 40)), at(40, 40));
+        assert_eq!(wide.constrain(from, at(260, 100)), at(260, 190));
+        assert_eq!(wide.constrain(from, at(100, 10)), at(260, 10));
+        assert_eq!(square.constrain(from, from), from);
+    }
+
+    #[test]
+    fn test_selection_aspect() {
+        let mut selection = Selection {
+            aspect: Some(Aspect {
+                width: 2,
+                height: 1,
+            }),
+            ..Default::default()
+        };
+        selection.begin(Pos { x: 0, y: 0 });
+        selection.update(Pos { x: 50, y: 5 });
+        assert_eq!(selection.to_region(), Some(region(0, 0, 50, 25)));
+        selection.end(Pos { x: 10, y: 40 });
+        assert_eq!(selection.to_region(), Some(region(0, 0, 80, 40)));
+        // shrinking along either axis keeps the ratio
+        selection.nudge(-10, 0);
+        assert_eq!(selection.to_region(), Some(region(0, 0, 70, 35)));
+        selection.nudge(0, -5);
+        assert_eq!(selection.to_region(), Some(region(0, 0, 60, 30)));
+
+        selection.select(Pos { x: 0, y: 0 }, Pos { x: 30, y: 30 });
+        assert_eq!(selection.to_region(), Some(region(0, 0, 30, 30)));
+    }
+
+    #[test]
+    fn test_selection_usable_region() {
+        let select = |w: i32, h: i32| {
+            let mut selection = Selection::default();
+            selection.begin(Pos { x: 10, y: 10 });
+            selection.end(Pos { x: 10 + w, y: 10 + h });
+            selection
+        };
+        assert_eq!(Selection::default().usable_region(), None);
+        assert_eq!(select(0, 0).usable_region(), None);
+        assert_eq!(select(1, 50).usable_region(), None);
+        assert_eq!(select(50, 1).usable_region(), None);
+        assert_eq!(select(50, 2).usable_region(), None);
+        assert_eq!(select(3, 3).usable_region(), Some(region(10, 10, 3, 3)));
+        // dragged up and to the left
+        assert_eq!(select(-50, -4).usable_region(), Some(region(-40, 6, 50, 4)));
+    }
+
+    #[test]
+    fn test_fill_frame() {
+        let mut pixmap = Pixmap::new(10, 10).unwrap();
+        let mut paint = tiny_skia::Paint::default();
+        paint.set_color(Color::WHITE);
+        let rect = tiny_skia::Rect::from_xywh(3., 3., 4., 4.).unwrap();
+        fill_frame(&mut pixmap, rect, 1., &paint, tiny_skia::Transform::identity());
+
+        let alpha = |x, y| pixmap.pixel(x, y).unwrap().alpha();
+        for (x, y) in [(2, 2), (7, 7), (2, 5), (5, 7)] {
+            assert_eq!(alpha(x, y), 0xff, "({x}, {y})");
+        }
+        for (x, y) in [(3, 3), (6, 6), (1, 1), (8, 5)] {
+            assert_eq!(alpha(x, y), 0, "({x}, {y})");
+        }
+    }
+
+    #[test]
+    fn test_fill_around() {
+        let mut pixmap = Pixmap::new(10, 10).unwrap();
+        pixmap.fill(Color::from_rgba8(0, 0, 0xff, 0xff));
+        let mut paint = tiny_skia::Paint::default();
+        paint.set_color(Color::WHITE);
+        let outer = tiny_skia::Rect::from_xywh(0., 0., 10., 10.).unwrap();
+        // sticking out on the right
+        let hole = tiny_skia::Rect::from_xywh(3., 3., 10., 4.);
+        let identity = tiny_skia::Transform::identity();
+        fill_around(&mut pixmap, outer, hole, &paint, identity);
+
+        let red = |x, y| pixmap.pixel(x, y).unwrap().red();
+        for (x, y) in [(0, 0), (9, 2), (2, 5), (5, 7), (9, 9)] {
+            assert_eq!(red(x, y), 0xff, "({x}, {y})");
+        }
+        for (x, y) in [(3, 3), (9, 6), (5, 5)] {
+            assert_eq!(red(x, y), 0, "({x}, {y})");
         }
+
+        // no hole dims everything
+        fill_around(&mut pixmap, outer, None, &paint, identity);
+        assert_eq!(pixmap.pixel(5, 5).unwrap().red(), 0xff);
+    }
+
+    #[test]
+    fn test_background_pixmap() {
+        // 4x2 image at scale 2 covering a 2x1 logical workspace
+        let image = RgbaImage::from_fn(4, 2, |x, y| image::Rgba([x as u8, y as u8, 0xff, 0xff]));
+        let background = Background { image, scale: 2. };
+        let pixmap = background.pixmap(region(1, 0, 2, 1), 1).unwrap();
+        let pixels = pixmap
+            .pixels()
+            .iter()
+            .map(|p| (p.red(), p.green(), p.blue(), p.alpha()))
+            .collect::<Vec<_>>();
+        // bgra, and transparent past the edge of the image
+        assert_eq!(pixels, [(0xff, 0, 2, 0xff), (0, 0, 0, 0)]);
+
+        // at the scale of the image, every pixel is kept
+        let pixmap = background.pixmap(region(1, 0, 1, 1), 2).unwrap();
+        let blue = pixmap.pixels().iter().map(|p| p.blue()).collect::<Vec<_>>();
+        assert_eq!(blue, [2, 3, 2, 3]);
     }
 
-    layer_state
-        .selection
-        .to_region()
-        .ok_or(anyhow!("failed to get selection"))
+    #[test]
+    fn test_dispatch_until() {
+        // a compositor that never says anything
+        let (client, _server) = std::os::unix::net::UnixStream::pair().unwrap();
+        let conn = Connection::from_socket(client).unwrap();
+        let mut queue = conn.new_event_queue::<()>();
+
+        let start = Instant::now();
+        let deadline = start + Duration::from_millis(50);
+        assert!(!dispatch_until(&mut queue, &mut (), deadline).unwrap());
+        assert!(start.elapsed() >= Duration::from_millis(50));
+        assert!(!dispatch_until(&mut queue, &mut (), deadline).unwrap());
+    }
+
+    #[test]
+    fn test_covered_by() {
+        let outputs = [region(0, 0, 100, 100), region(100, 0, 100, 50)];
+        assert!(covered_by(region(10, 10, 20, 20), &outputs));
+        assert!(covered_by(region(90, 10, 20, 20), &outputs));
+        // below the smaller output
+        assert!(!covered_by(region(90, 40, 20, 20), &outputs));
+        assert!(!covered_by(region(-5, 0, 10, 10), &outputs));
+        assert!(!covered_by(region(10, 10, 20, 20), &[]));
+    }
+
+    #[test]
+    fn test_clamp_to() {
+        let outputs = [region(0, 0, 100, 100), region(100, 0, 100, 50)];
+        assert_eq!(clamp_to(region(10, 10, 20, 20), &outputs), Some(region(10, 10, 20, 20)));
+        // dragged off the top-left corner
+        assert_eq!(clamp_to(region(-3, -1, 20, 20), &outputs), Some(region(0, 0, 17, 19)));
+        // and off the bottom-right one
+        assert_eq!(clamp_to(region(90, 90, 20, 20), &outputs), Some(region(90, 90, 10, 10)));
+        // below the shorter output
+        assert_eq!(clamp_to(region(150, 40, 10, 30), &outputs), Some(region(150, 40, 10, 10)));
+        assert_eq!(clamp_to(region(190, 90, 20, 20), &outputs), None);
+        // across both, keeping the part below the shorter one on the taller
+        assert_eq!(clamp_to(region(90, 40, 20, 20), &outputs), Some(region(90, 40, 20, 20)));
+        assert_eq!(clamp_to(region(300, 300, 5, 5), &outputs), None);
+        assert_eq!(clamp_to(region(10, 10, 20, 20), &[]), None);
+    }
+
+    #[test]
+    fn test_split() {
+        let outputs = [(region(0, 0, 1920, 1080), 1.), (region(1920, 0, 1280, 720), 2.)];
+        // within one output, captured in one go
+        assert_eq!(
+            split(region(10, 10, 100, 100), &outputs),
+            Some(Area::single(region(10, 10, 100, 100)))
+        );
+        // across both
+        assert_eq!(
+            split(region(1900, 10, 100, 100), &outputs),
+            Some(Area {
+                region: region(1900, 10, 100, 100),
+                logical: region(1900, 10, 100, 100),
+                pieces: vec![
+                    Piece {
+                        source: region(1900, 10, 20, 100),
+                        target: region(0, 0, 20, 100),
+                    },
+                    Piece {
+                        source: region(3840, 20, 160, 200),
+                        target: region(20, 0, 80, 100),
+                    },
+                ],
+                window: None,
+            })
+        );
+    }
+
+    #[test]
+    fn test_window_at() {
+        let at = |x, y| Pos { x, y };
+        // a dialog over the middle of a larger window
+        let windows = [region(0, 0, 800, 600), region(200, 200, 300, 100)];
+        assert_eq!(window_at(&windows, at(10, 10)), Some(0));
+        assert_eq!(window_at(&windows, at(250, 250)), Some(1));
+        // the right and bottom edges are outside
+        assert_eq!(window_at(&windows, at(500, 250)), Some(0));
+        assert_eq!(window_at(&windows, at(800, 10)), None);
+        assert_eq!(window_at(&[], at(10, 10)), None);
+    }
+
+    #[test]
+    fn test_transformed_size() {
+        let size = (1920, 1080);
+        assert_eq!(transformed_size(size, Transform::Normal), (1920, 1080));
+        assert_eq!(transformed_size(size, Transform::_90), (1080, 1920));
+        assert_eq!(transformed_size(size, Transform::_180), (1920, 1080));
+        assert_eq!(transformed_size(size, Transform::_270), (1080, 1920));
+        assert_eq!(transformed_size(size, Transform::Flipped90), (1080, 1920));
+    }
+
+    #[test]
+    fn test_to_physical_fractional_scale() {
+        let outputs = [(region(0, 0, 1707, 960), 1.5)];
+        assert_eq!(
+            to_physical(region(100, 50, 200, 101), outputs),
+            Some(region(150, 75, 300, 152))
+        );
+    }
+
+    #[test]
+    fn test_to_physical_uses_origin_output() {
+        let outputs = [(region(0, 0, 1920, 1080), 1.), (region(1920, 0, 1280, 720), 2.)];
+        // origin on the first output, spanning into the second
+        assert_eq!(
+            to_physical(region(1900, 10, 100, 100), outputs),
+            Some(region(1900, 10, 100, 100))
+        );
+        // origin on the second output
+        assert_eq!(
+            to_physical(region(2000, 10, 100, 100), outputs),
+            Some(region(4000, 20, 200, 200))
+        );
+    }
 }
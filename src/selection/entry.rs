@@ -0,0 +1,78 @@
+//! Coordinates typed on the overlay, for when the exact region is known but
+//! worth seeing before it is captured.
+use super::Region;
+
+/// longest text accepted, plenty for four numbers
+const MAX_LEN: usize = 48;
+
+/// The `x,y,w,h` typed so far.
+#[derive(Debug, Default, Clone, PartialEq, Eq)]
+pub struct Entry {
+    text: String,
+}
+impl Entry {
+    pub fn text(&self) -> &str {
+        &self.text
+    }
+
+    /// Type `c`, anything but digits, signs and separators is ignored. A
+    /// space separates the numbers like a comma does.
+    pub fn push(&mut self, c: char) {
+        let c = if c == ' ' { ',' } else { c };
+        if (c.is_ascii_digit() || c == ',' || c == '-') && self.text.len() < MAX_LEN {
+            self.text.push(c);
+        }
+    }
+
+    pub fn pop(&mut self) {
+        self.text.pop();
+    }
+
+    /// The region typed, once all four numbers are there and make sense.
+    pub fn region(&self) -> Option<Region> {
+        let parts = self.text.split(',').collect::<Vec<_>>();
+        let [x, y, w, h] = parts[..] else {
+            return None;
+        };
+        Region::from_xywh(
+            x.parse().ok()?,
+            y.parse().ok()?,
+            w.parse().ok()?,
+            h.parse().ok()?,
+        )
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn typed(text: &str) -> Entry {
+        let mut entry = Entry::default();
+        text.chars().for_each(|c| entry.push(c));
+        entry
+    }
+
+    #[test]
+    fn test_push() {
+        assert_eq!(typed("10 20,x30").text(), "10,20,30");
+        let mut entry = typed("-5,1");
+        entry.pop();
+        entry.pop();
+        assert_eq!(entry.text(), "-5");
+        assert_eq!(typed(&"1".repeat(100)).text().len(), MAX_LEN);
+    }
+
+    #[test]
+    fn test_region() {
+        assert_eq!(
+            typed("-100,20,640,480").region(),
+            Region::from_xywh(-100, 20, 640, 480)
+        );
+        assert_eq!(typed("100,20,640").region(), None);
+        assert_eq!(typed("100,20,640,").region(), None);
+        assert_eq!(typed("100,20,-640,480").region(), None);
+        assert_eq!(typed("100,20,0,480").region(), None);
+        assert_eq!(typed("1,2,3,4,5").region(), None);
+    }
+}
@@ -0,0 +1,130 @@
+//! A tiny built-in bitmap font to label the selection with its size, so the
+//! overlay doesn't depend on any font being installed.
+use tiny_skia::{Color, Paint, Pixmap, Rect, Transform};
+
+use super::Region;
+
+const GLYPH_WIDTH: u32 = 5;
+const GLYPH_HEIGHT: u32 = 7;
+const PIXEL_SIZE: u32 = 2; // label pixels per glyph pixel
+const PADDING: u32 = 4;
+const MARGIN: i32 = 4; // distance to the selection
+
+/// Rows of a glyph, the highest of the five bits is the leftmost pixel.
+fn glyph(c: char) -> Option<[u8; GLYPH_HEIGHT as usize]> {
+    let rows = match c {
+        '0' => [0x0e, 0x11, 0x13, 0x15, 0x19, 0x11, 0x0e],
+        '1' => [0x04, 0x0c, 0x04, 0x04, 0x04, 0x04, 0x0e],
+        '2' => [0x0e, 0x11, 0x01, 0x02, 0x04, 0x08, 0x1f],
+        '3' => [0x1f, 0x02, 0x04, 0x02, 0x01, 0x11, 0x0e],
+        '4' => [0x02, 0x06, 0x0a, 0x12, 0x1f, 0x02, 0x02],
+        '5' => [0x1f, 0x10, 0x1e, 0x01, 0x01, 0x11, 0x0e],
+        '6' => [0x06, 0x08, 0x10, 0x1e, 0x11, 0x11, 0x0e],
+        '7' => [0x1f, 0x01, 0x02, 0x04, 0x08, 0x08, 0x08],
+        '8' => [0x0e, 0x11, 0x11, 0x0e, 0x11, 0x11, 0x0e],
+        '9' => [0x0e, 0x11, 0x11, 0x0f, 0x01, 0x02, 0x0c],
+        '×' => [0x00, 0x11, 0x0a, 0x04, 0x0a, 0x11, 0x00],
+        ',' => [0x00, 0x00, 0x00, 0x00, 0x0c, 0x04, 0x08],
+        '-' => [0x00, 0x00, 0x00, 0x1f, 0x00, 0x00, 0x00],
+        '_' => [0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x1f],
+        _ => return None,
+    };
+    Some(rows)
+}
+
+/// Size of the label showing `text`, padding included.
+pub fn size(text: &str) -> (u32, u32) {
+    let glyphs = text.chars().count() as u32;
+    let text_width = (glyphs * (GLYPH_WIDTH + 1)).saturating_sub(1) * PIXEL_SIZE;
+    (
+        text_width + 2 * PADDING,
+        GLYPH_HEIGHT * PIXEL_SIZE + 2 * PADDING,
+    )
+}
+
+/// Where to put a label of `size` for `selection`: below it, or above when
+/// there is no room left, and always inside `bounds`.
+pub fn place(selection: Region, bounds: Region, size: (u32, u32)) -> Option<Region> {
+    let (w, h) = (size.0 as i32, size.1 as i32);
+    let mut y = selection.bottom() + MARGIN;
+    if y + h > bounds.bottom() {
+        y = selection.top() - MARGIN - h;
+    }
+    let x = selection.left().min(bounds.right() - w).max(bounds.left());
+    let y = y.min(bounds.bottom() - h).max(bounds.top());
+    Region::from_xywh(x, y, size.0, size.1)
+}
+
+/// Draw `text` on a dark box filling `rect`, `transform` maps it into `pixmap`.
+pub fn draw(pixmap: &mut Pixmap, text: &str, rect: Region, transform: Transform) {
+    let mut paint = Paint::default();
+    paint.set_color(Color::from_rgba8(0, 0, 0, 0xc0));
+    pixmap.fill_rect(rect.to_rect(), &paint, transform, None);
+
+    paint.set_color(Color::WHITE);
+    let pixel = PIXEL_SIZE as f32;
+    let left = (rect.x() + PADDING as i32) as f32;
+    let top = (rect.y() + PADDING as i32) as f32;
+    for (i, rows) in text.chars().filter_map(glyph).enumerate() {
+        let origin = left + (i as u32 * (GLYPH_WIDTH + 1)) as f32 * pixel;
+        for (row, bits) in rows.iter().enumerate() {
+            for col in 0..GLYPH_WIDTH {
+                if bits & (1 << (GLYPH_WIDTH - 1 - col)) == 0 {
+                    continue;
+                }
+                let x = origin + col as f32 * pixel;
+                let y = top + row as f32 * pixel;
+                if let Some(dot) = Rect::from_xywh(x, y, pixel, pixel) {
+                    pixmap.fill_rect(dot, &paint, transform, None);
+                }
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn region(x: i32, y: i32, w: u32, h: u32) -> Region {
+        Region::from_xywh(x, y, w, h).unwrap()
+    }
+
+    #[test]
+    fn test_size() {
+        // 3 glyphs, 2 gaps
+        assert_eq!(
+            size("1×2"),
+            (17 * PIXEL_SIZE + 2 * PADDING, 7 * PIXEL_SIZE + 2 * PADDING)
+        );
+    }
+
+    #[test]
+    fn test_place() {
+        let bounds = region(0, 0, 1920, 1080);
+        // below the selection
+        assert_eq!(
+            place(region(100, 100, 200, 200), bounds, (50, 20)),
+            Some(region(100, 304, 50, 20))
+        );
+        // above when the bottom edge is reached
+        assert_eq!(
+            place(region(100, 1000, 200, 80), bounds, (50, 20)),
+            Some(region(100, 976, 50, 20))
+        );
+        // pushed back in from the right, and down when the selection spans the output
+        assert_eq!(
+            place(region(1900, 0, 20, 1080), bounds, (50, 20)),
+            Some(region(1870, 0, 50, 20))
+        );
+        // on a second output
+        assert_eq!(
+            place(
+                region(1900, 10, 100, 100),
+                region(1920, 0, 1280, 720),
+                (50, 20)
+            ),
+            Some(region(1920, 114, 50, 20))
+        );
+    }
+}
@@ -0,0 +1,117 @@
+//! A loupe next to the pointer showing the frozen screen magnified, to put
+//! the edges of a selection on exactly the right pixel.
+use tiny_skia::{Color, FilterQuality, Paint, Pattern, Pixmap, Rect, SpreadMode, Transform};
+
+use super::Region;
+
+const ZOOM: i32 = 8; // loupe pixels per logical pixel
+const CELLS: i32 = 15; // logical pixels across, odd to have one in the middle
+const SIZE: i32 = ZOOM * CELLS;
+const OFFSET: i32 = 24; // distance to the pointer
+
+/// Where to put the loupe for `pointer`: below and right of it, on the other
+/// side when there is no room left, and always inside `bounds`.
+pub fn place(pointer: (i32, i32), bounds: Region) -> Option<Region> {
+    let along = |p: i32, low: i32, high: i32| {
+        let mut v = p + OFFSET;
+        if v + SIZE > high {
+            v = p - OFFSET - SIZE;
+        }
+        v.min(high - SIZE).max(low)
+    };
+    let x = along(pointer.0, bounds.left(), bounds.right());
+    let y = along(pointer.1, bounds.top(), bounds.bottom());
+    Region::from_xywh(x, y, SIZE as u32, SIZE as u32)
+}
+
+/// Draw the pixels around `pointer` magnified into `rect`, with a crosshair
+/// on the one under it.
+///
+/// `background` holds `buffer_scale` pixels per logical pixel of the output
+/// whose top-left corner is `origin`, `transform` maps into `pixmap`.
+#[allow(clippy::too_many_arguments)]
+pub fn draw(
+    pixmap: &mut Pixmap,
+    background: &Pixmap,
+    origin: (i32, i32),
+    buffer_scale: i32,
+    pointer: (i32, i32),
+    rect: Region,
+    color: Color,
+    transform: Transform,
+) {
+    let zoom = ZOOM as f32;
+    let middle = ((CELLS / 2) * ZOOM) as f32;
+    // top-left corner of the pixel under the pointer
+    let (cx, cy) = (rect.x() as f32 + middle, rect.y() as f32 + middle);
+
+    // background pixels to logical ones, then magnified around the pointer
+    let inverse = 1. / buffer_scale.max(1) as f32;
+    let magnify = Transform::from_translate(cx, cy)
+        .pre_scale(zoom, zoom)
+        .pre_translate((origin.0 - pointer.0) as f32, (origin.1 - pointer.1) as f32)
+        .pre_scale(inverse, inverse);
+    let paint = Paint {
+        shader: Pattern::new(
+            background.as_ref(),
+            SpreadMode::Pad,
+            FilterQuality::Nearest,
+            1.,
+            magnify,
+        ),
+        ..Default::default()
+    };
+    pixmap.fill_rect(rect.to_rect(), &paint, transform, None);
+
+    // crisp lines, tiny-skia also trips over anti-aliasing thin rects
+    let mut paint = Paint {
+        anti_alias: false,
+        ..Default::default()
+    };
+    paint.set_color(color);
+    // crosshair up to the middle pixel, leaving it visible
+    let center = zoom / 2.;
+    let (left, top) = (rect.x() as f32, rect.y() as f32);
+    for line in [
+        Rect::from_xywh(left, cy + center, middle, 1.),
+        Rect::from_xywh(cx + zoom, cy + center, middle, 1.),
+        Rect::from_xywh(cx + center, top, 1., middle),
+        Rect::from_xywh(cx + center, cy + zoom, 1., middle),
+    ]
+    .into_iter()
+    .flatten()
+    {
+        pixmap.fill_rect(line, &paint, transform, None);
+    }
+
+    for frame in [Rect::from_xywh(cx, cy, zoom, zoom), Some(rect.to_rect())]
+        .into_iter()
+        .flatten()
+    {
+        super::fill_frame(pixmap, frame, 1., &paint, transform);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_place() {
+        let bounds = Region::from_xywh(0, 0, 1000, 500).unwrap();
+        let at = |x, y| Region::from_xywh(x, y, SIZE as u32, SIZE as u32);
+
+        assert_eq!(place((100, 100), bounds), at(100 + OFFSET, 100 + OFFSET));
+        // flipped near the right and bottom edges
+        assert_eq!(
+            place((990, 490), bounds),
+            at(990 - OFFSET - SIZE, 490 - OFFSET - SIZE)
+        );
+        // no room either side
+        let small = Region::from_xywh(0, 0, SIZE as u32 + 10, SIZE as u32 + 10).unwrap();
+        assert_eq!(place((60, 60), small), at(0, 0));
+        // other outputs than the first
+        let right = Region::from_xywh(1000, 0, 1000, 500).unwrap();
+        assert_eq!(place((1010, 10), right), at(1010 + OFFSET, 10 + OFFSET));
+    }
+}
@@ -0,0 +1,152 @@
+//! `--serve` keeps one process on the session bus to take the scans asked
+//! for with `--client`, saving the startup of a fresh `rq` on every press
+//! of a keybinding.
+use anyhow::{anyhow, Result};
+use std::sync::{
+    atomic::{AtomicBool, Ordering},
+    Arc,
+};
+use zbus::{fdo, interface, proxy, DBusError};
+
+use crate::capture::unblock;
+use crate::decode::{DecodeResult, Format};
+use crate::logger::{info, warn};
+use crate::selection::Cancelled;
+
+const NAME: &str = "io.github.EvinceMoi.Rq";
+const PATH: &str = "/io/github/EvinceMoi/Rq";
+
+/// Select, capture and decode once, returning the codes decoded.
+pub type Scan = dyn Fn() -> Result<Vec<DecodeResult>> + Send + Sync;
+
+/// A decoded code on the bus: its text, the name of its format and its
+/// corners.
+type Code = (String, String, Vec<(i32, i32)>);
+
+fn to_code(result: DecodeResult) -> Code {
+    (result.text, result.format.name().into(), result.bounds.into())
+}
+
+fn from_code((text, format, bounds): Code) -> Result<DecodeResult> {
+    Ok(DecodeResult {
+        text,
+        format: Format::from_name(&format).ok_or(anyhow!("unknown format {format}"))?,
+        bounds: bounds
+            .try_into()
+            .map_err(|bounds: Vec<_>| anyhow!("a code has {} corners", bounds.len()))?,
+    })
+}
+
+#[derive(Debug, DBusError)]
+#[zbus(prefix = "io.github.EvinceMoi.Rq.Error")]
+enum ScanError {
+    #[zbus(error)]
+    ZBus(zbus::Error),
+    /// the user cancelled the selection
+    Cancelled(String),
+    /// another scan is running
+    Busy(String),
+    Failed(String),
+}
+
+struct Service {
+    scan: Arc<Scan>,
+    busy: Arc<AtomicBool>,
+}
+
+#[interface(name = "io.github.EvinceMoi.Rq")]
+impl Service {
+    /// Let the user select a region and return the codes in it, none when
+    /// nothing was found or selected before the timeout.
+    async fn scan(&self) -> Result<Vec<Code>, ScanError> {
+        // one overlay at a time
+        if self.busy.swap(true, Ordering::SeqCst) {
+            return Err(ScanError::Busy("a scan is already running".into()));
+        }
+        let (scan, busy) = (self.scan.clone(), self.busy.clone());
+        let scanned = unblock(move || {
+            let scanned = scan();
+            busy.store(false, Ordering::SeqCst);
+            scanned
+        })
+        .await;
+        match scanned {
+            Ok(decoded) => Ok(decoded.into_iter().map(to_code).collect()),
+            Err(err) if err.is::<Cancelled>() => Err(ScanError::Cancelled(err.to_string())),
+            Err(err) => {
+                warn!("scan failed: {err:#}");
+                Err(ScanError::Failed(format!("{err:#}")))
+            }
+        }
+    }
+}
+
+#[proxy(
+    default_service = "io.github.EvinceMoi.Rq",
+    interface = "io.github.EvinceMoi.Rq",
+    default_path = "/io/github/EvinceMoi/Rq"
+)]
+trait Rq {
+    fn scan(&self) -> zbus::Result<Vec<Code>>;
+}
+
+/// Take the bus name and run `scan` for every client calling, until killed.
+pub async fn serve(scan: Box<Scan>) -> Result<()> {
+    let service = Service {
+        scan: scan.into(),
+        busy: Arc::new(AtomicBool::new(false)),
+    };
+    let _conn = zbus::connection::Builder::session()?
+        .serve_at(PATH, service)?
+        .name(NAME)?
+        .build()
+        .await
+        .map_err(|err| {
+            anyhow!("failed to serve as {NAME}, is another rq --serve running? ({err})")
+        })?;
+    info!("waiting for scans as {NAME}");
+    std::future::pending().await
+}
+
+/// Ask the running `rq --serve` to scan, `None` when there is none.
+pub async fn scan() -> Result<Option<Vec<DecodeResult>>> {
+    // no bus, no service: wlroots captures still work without one
+    let Ok(conn) = zbus::Connection::session().await else {
+        return Ok(None);
+    };
+    let dbus = fdo::DBusProxy::new(&conn).await?;
+    if !dbus.name_has_owner(NAME.try_into()?).await? {
+        return Ok(None);
+    }
+    let proxy = RqProxy::new(&conn).await?;
+    match proxy.scan().await {
+        Ok(codes) => codes.into_iter().map(from_code).collect::<Result<_>>().map(Some),
+        Err(zbus::Error::MethodError(name, message, _)) => {
+            if name.as_str() == format!("{NAME}.Error.Cancelled") {
+                return Err(Cancelled.into());
+            }
+            let message = message.unwrap_or_else(|| name.to_string());
+            Err(anyhow!("rq --serve failed to scan: {message}"))
+        }
+        Err(err) => Err(err.into()),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_code() {
+        let result = DecodeResult {
+            text: "WIFI:S:home;T:WPA;P:secret;;".into(),
+            format: Format::Qr,
+            bounds: [(1, 2), (30, 2), (30, 40), (1, 40)],
+        };
+        let code = to_code(result.clone());
+        assert_eq!(code.1, "qr");
+        assert_eq!(from_code(code).unwrap(), result);
+        assert!(from_code(("x".into(), "pdf417".into(), vec![(0, 0); 4])).is_err());
+        assert!(from_code(("x".into(), "qr".into(), vec![(0, 0); 3])).is_err());
+    }
+}
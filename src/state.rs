@@ -0,0 +1,78 @@
+//! Small bits remembered between runs, kept under `$XDG_STATE_HOME/rq`.
+use anyhow::{anyhow, Context, Result};
+use std::{
+    fs,
+    path::{Path, PathBuf},
+};
+
+use crate::selection::Region;
+use crate::xdg;
+
+const LAST_REGION: &str = "last-region";
+
+/// `$XDG_STATE_HOME/rq`, or `~/.local/state/rq` when it is unset.
+fn state_dir() -> Option<PathBuf> {
+    Some(xdg::base_dir("XDG_STATE_HOME", ".local/state")?.join("rq"))
+}
+
+/// The region last confirmed in the overlay, if there is one.
+pub fn last_region() -> Option<Region> {
+    load_region(&state_dir()?.join(LAST_REGION))
+}
+
+/// Remember `region` for `--last`.
+pub fn save_last_region(region: Region) -> Result<()> {
+    let dir = state_dir().ok_or(anyhow!("neither XDG_STATE_HOME nor HOME is set"))?;
+    save_region(&dir.join(LAST_REGION), region)
+}
+
+fn load_region(path: &Path) -> Option<Region> {
+    let text = fs::read_to_string(path).ok()?;
+    let [x, y, w, h] = text.trim().split(',').collect::<Vec<_>>()[..] else {
+        return None;
+    };
+    Region::from_xywh(
+        x.parse().ok()?,
+        y.parse().ok()?,
+        w.parse().ok()?,
+        h.parse().ok()?,
+    )
+}
+
+fn save_region(path: &Path, region: Region) -> Result<()> {
+    if let Some(dir) = path.parent() {
+        fs::create_dir_all(dir)
+            .with_context(|| format!("failed to create directory {}", dir.display()))?;
+    }
+    let text = format!(
+        "{},{},{},{}\n",
+        region.x(),
+        region.y(),
+        region.width(),
+        region.height()
+    );
+    fs::write(path, text).with_context(|| format!("failed to write {}", path.display()))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_region_roundtrip() {
+        let dir = std::env::temp_dir().join(format!("rq-state-test-{}", std::process::id()));
+        let path = dir.join("nested").join(LAST_REGION);
+        assert_eq!(load_region(&path), None);
+
+        let region = Region::from_xywh(-10, 20, 300, 400).unwrap();
+        save_region(&path, region).unwrap();
+        assert_eq!(load_region(&path), Some(region));
+
+        fs::write(&path, "1,2,3").unwrap();
+        assert_eq!(load_region(&path), None);
+        fs::write(&path, "1,2,0,4").unwrap();
+        assert_eq!(load_region(&path), None);
+
+        fs::remove_dir_all(&dir).unwrap();
+    }
+}
@@ -0,0 +1,16 @@
+//! The XDG base directories, where config and state files go.
+use std::{env, path::PathBuf};
+
+/// The directory in the `var` environment variable, e.g. `XDG_CONFIG_HOME`,
+/// or `fallback` in the home directory when it is unset. Relative paths are
+/// ignored, as the spec asks.
+pub fn base_dir(var: &str, fallback: &str) -> Option<PathBuf> {
+    let absolute = |dir: PathBuf| Some(dir).filter(|dir| dir.is_absolute());
+    env::var_os(var)
+        .map(PathBuf::from)
+        .and_then(absolute)
+        .or_else(|| {
+            let home = env::var_os("HOME").map(PathBuf::from).and_then(absolute)?;
+            Some(home.join(fallback))
+        })
+}